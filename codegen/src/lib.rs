@@ -0,0 +1,202 @@
+// Copyright (c) 2018  Brendan Molloy <brendan@bbqsrc.net>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Procedural macros used internally by `cucumber_rust`.
+//!
+//! Nothing in here is meant to be used directly; the macros are re-exported by
+//! `cucumber_rust` and invoked by its `steps!` macro.
+
+extern crate proc_macro;
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenTree;
+use quote::quote;
+use regex::Regex;
+use syn::parse::{Parse, ParseStream};
+use syn::punctuated::Punctuated;
+use syn::{braced, parenthesized, token, Expr, Lit, LitStr, Token, Type};
+
+struct StepRegex {
+    krate: TokenTree,
+    regex: Expr,
+    args: Option<StepArgs>,
+}
+
+enum StepArgs {
+    Typed(Punctuated<Type, Token![,]>),
+    Named(Type),
+}
+
+impl Parse for StepRegex {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let krate = input.parse()?;
+        input.parse::<Token![,]>()?;
+        let regex = input.parse()?;
+
+        let args = if input.is_empty() {
+            None
+        } else {
+            input.parse::<Token![,]>()?;
+            let content;
+
+            if input.peek(token::Brace) {
+                braced!(content in input);
+                Some(StepArgs::Named(content.parse()?))
+            } else {
+                parenthesized!(content in input);
+                Some(StepArgs::Typed(
+                    content.parse_terminated(Type::parse, Token![,])?,
+                ))
+            }
+        };
+
+        Ok(StepRegex { krate, regex, args })
+    }
+}
+
+/// Validates a step regex at compile time and expands to a `&'static Regex`
+/// that is compiled only once, on first use.
+///
+/// Invoked as `step_regex!($crate, "pattern")`, or with the typed argument
+/// list of the step as `step_regex!($crate, "pattern", (usize, String))`, in
+/// which case the number of capture groups must equal the number of types.
+/// A step taking a struct built from named captures is invoked as
+/// `step_regex!($crate, "pattern", { MyStruct })`, and its regex must have at
+/// least one named capture group.
+///
+/// A regex that is not a string literal, such as a `const`, cannot be checked
+/// at compile time, and is checked when it is compiled on first use instead.
+#[proc_macro]
+pub fn step_regex(input: TokenStream) -> TokenStream {
+    let StepRegex { krate, regex, args } = syn::parse_macro_input!(input as StepRegex);
+
+    let regex = match literal(&regex) {
+        Some(regex) => regex,
+        None => return runtime_checked(&krate, &regex, args.as_ref()).into(),
+    };
+    let pattern = regex.value();
+
+    let compiled = match Regex::new(&pattern) {
+        Ok(v) => v,
+        Err(e) => {
+            return syn::Error::new(
+                regex.span(),
+                format!("`{}` is not a valid regular expression: {}", pattern, e),
+            )
+            .to_compile_error()
+            .into();
+        }
+    };
+
+    match args {
+        Some(StepArgs::Typed(arg_types)) => {
+            let capture_count = compiled.captures_len() - 1;
+
+            if capture_count != arg_types.len() {
+                return syn::Error::new(
+                    regex.span(),
+                    format!(
+                        "`{}` has {} capture group(s) but the step declares {} typed argument(s)",
+                        pattern,
+                        capture_count,
+                        arg_types.len()
+                    ),
+                )
+                .to_compile_error()
+                .into();
+            }
+        }
+        Some(StepArgs::Named(args_type)) if compiled.capture_names().flatten().next().is_none() => {
+            return syn::Error::new(
+                regex.span(),
+                format!(
+                    "`{}` has no named capture groups to fill `{}` from",
+                    pattern,
+                    quote!(#args_type)
+                ),
+            )
+            .to_compile_error()
+            .into();
+        }
+        _ => {}
+    }
+
+    let expanded = quote! {
+        {
+            static REGEX: ::std::sync::OnceLock<#krate::regex::Regex> =
+                ::std::sync::OnceLock::new();
+            REGEX.get_or_init(|| #krate::regex::Regex::new(#regex).unwrap())
+        }
+    };
+
+    expanded.into()
+}
+
+/// Expands to the same `&'static Regex` for a regex that is not a literal,
+/// checking it when it is compiled, and evaluating it only then.
+fn runtime_checked(
+    krate: &TokenTree,
+    regex: &Expr,
+    args: Option<&StepArgs>,
+) -> proc_macro2::TokenStream {
+    let check = match args {
+        Some(StepArgs::Typed(arg_types)) => {
+            let arg_count = arg_types.len();
+            quote! {
+                let capture_count = regex.captures_len() - 1;
+                if capture_count != #arg_count {
+                    panic!(
+                        "`{}` has {} capture group(s) but the step declares {} typed argument(s)",
+                        pattern, capture_count, #arg_count
+                    );
+                }
+            }
+        }
+        Some(StepArgs::Named(args_type)) => {
+            let args_type = quote!(#args_type).to_string();
+            quote! {
+                if regex.capture_names().flatten().next().is_none() {
+                    panic!(
+                        "`{}` has no named capture groups to fill `{}` from",
+                        pattern, #args_type
+                    );
+                }
+            }
+        }
+        None => quote! {},
+    };
+
+    quote! {
+        {
+            static REGEX: ::std::sync::OnceLock<#krate::regex::Regex> =
+                ::std::sync::OnceLock::new();
+            REGEX.get_or_init(|| {
+                let pattern: &str = #regex;
+                let regex = #krate::regex::Regex::new(pattern).unwrap_or_else(|e| {
+                    panic!("`{}` is not a valid regular expression: {}", pattern, e)
+                });
+                #check
+                regex
+            })
+        }
+    }
+}
+
+/// The string literal that `regex` is, if it is one, looking through the
+/// invisible groups that `macro_rules!` wraps some fragments in.
+fn literal(regex: &Expr) -> Option<LitStr> {
+    match regex {
+        Expr::Lit(expr) => match &expr.lit {
+            Lit::Str(lit) => Some(lit.clone()),
+            _ => None,
+        },
+        Expr::Group(group) => literal(&group.expr),
+        Expr::Paren(paren) => literal(&paren.expr),
+        _ => None,
+    }
+}
@@ -8,6 +8,129 @@ Feature: Basic functionality
     Given a thing
     When something goes wrong
 
+  Scenario: optional captures
+    Given a thing
+    Then the apple count is 3
+    And the apple count is unknown
+
+  Scenario: named captures
+    Given a thing
+    Then alice has 3 apples
+    And alice has 3 green apples
+
+  @tables
+  Scenario: data tables
+    Given a thing
+    Then things can also be data tables
+      | key | value |
+      | a   | fizz  |
+      | b   | buzz  |
+    And tables can be read as structs
+      | name  | count |
+      | alice | 3     |
+      | bob   | 5     |
+    And tables can be read as keys and values
+      | a | fizz |
+      | b | buzz |
+
+  Scenario: docstrings
+    Given a thing
+    Then docstrings can be parsed by content type
+      """json
+      {
+        "name": "cucumber",
+        "retries": 3
+      }
+      """
+
+  Scenario: panics in spawned threads
+    Given a thing
+    Then a panic in a thread it spawns goes to the panic hook
+
+  Scenario Outline: outlines
+    Given a thing
+    When test <word> regex
+    Then alice has 3 <colour> apples
+
+    Examples:
+      | word | colour |
+      | foo  | green  |
+      | bar  | green  |
+
+  Scenario: docstrings after comments
+    Given a thing
+    Then docstrings keep their content type past comments and threads
+      # A comment before the docstring
+      """json
+      { "name": "cucumber" }
+      """
+
+  Scenario: regexes from constants
+    Given a thing
+    Then there are 2 pears
+    And constant regexes are checked when first compiled
+
+  Scenario: outputs registered by name
+    Given a thing
+    Then the count output counts 1 scenario run at features/test.feature:3
+
+  Scenario: reproducible random order
+    Given a thing
+    Then running features/test.feature:3 features/test.feature:11 features/test.feature:16 features/test.feature:68 in a random order prints the seed to repeat it with
+
+  Scenario: isolated scenarios that abort
+    Given a thing
+    Then running tests/features/isolation.feature isolated reports the abort and runs the rest
+
+  Scenario: docstrings between backticks
+    Given a thing
+    Then docstrings can be parsed by content type
+      ```json
+      {
+        "name": "cucumber",
+        "retries": 3
+      }
+      ```
+
+  Scenario Outline: docstrings in outlines
+    Given a thing
+    Then docstrings in outlines keep their content type
+      """json
+      { "name": "<name>" }
+      """
+
+    Examples:
+      | name     |
+      | cucumber |
+
+  Scenario: several regexes matching a step
+    Given a thing
+    Then several regexes match
+
+  Scenario: outlines run once per row of their examples
+    Given a thing
+    Then the count output counts 2 scenarios run at features/test.feature:50
+
+  Scenario: reports of a run
+    Given a thing
+    Then the json report of tests/features/reports.feature has its scenarios and their steps
+    And the junit report of tests/features/reports.feature has a testcase per scenario
+    And the html report of tests/features/reports.feature has its scenarios by status
+    And the message report of tests/features/reports.feature links pickles, test cases and steps
+    And the rerun file of tests/features/reports.feature lists its failed scenario
+
+  Scenario: features that fail to parse
+    Given a thing
+    Then the json report of tests/features/broken.feature leaves it out
+
+  Scenario: shards of a run
+    Given a thing
+    Then running tests/features/shards/one.feature tests/features/shards/two.feature in 2 shards runs each feature in one of them
+
+  Scenario: scenarios selected by a line of theirs
+    Given a thing
+    Then the count output counts 1 scenario run at tests/features/reports.feature:11
+
   Rule: A rule
     
     Scenario: a scenario inside a rule
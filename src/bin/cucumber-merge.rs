@@ -0,0 +1,51 @@
+use std::fs;
+use std::process;
+
+use clap::{App, Arg};
+use cucumber_rust::merge::merge_reports;
+
+fn main() {
+    let matches = App::new("cucumber-merge")
+        .version(env!("CARGO_PKG_VERSION"))
+        .about("Merges Cucumber JSON or JUnit reports, such as those of several shards, into one")
+        .arg(
+            Arg::with_name("output")
+                .short("o")
+                .long("output")
+                .value_name("path")
+                .help("The file to write the merged report to, instead of stdout")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("reports")
+                .value_name("report")
+                .help("The reports to merge. A scenario in several is taken from the last")
+                .required(true)
+                .multiple(true),
+        )
+        .get_matches();
+
+    let reports = matches
+        .values_of("reports")
+        .unwrap()
+        .map(|path| {
+            fs::read_to_string(path).unwrap_or_else(|e| {
+                eprintln!("Failed to read {}: {}", path, e);
+                process::exit(1);
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let merged = merge_reports(&reports).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(1);
+    });
+
+    match matches.value_of("output") {
+        Some(path) => fs::write(path, merged).unwrap_or_else(|e| {
+            eprintln!("Failed to write {}: {}", path, e);
+            process::exit(1);
+        }),
+        None => print!("{}", merged),
+    }
+}
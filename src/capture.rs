@@ -0,0 +1,193 @@
+//! Conversion of regex captures into the typed arguments of `steps!`.
+//!
+//! A typed argument is either `T` or `Option<T>` for some `T: FromStr`. The two
+//! cases are told apart by method resolution: `ParseOptional` is implemented on
+//! `Capture<Option<T>>` itself, so it is found before `ParseRequired`, which is
+//! only implemented on `&Capture<T>`.
+//!
+//! Steps taking a struct instead have it deserialized from the named capture
+//! groups, one field per group.
+
+use std::marker::PhantomData;
+use std::str::FromStr;
+
+use regex::Captures;
+use serde::de::value::{Error, MapDeserializer};
+use serde::de::{self, Deserialize, Deserializer, IntoDeserializer, Unexpected, Visitor};
+use serde::forward_to_deserialize_any;
+
+pub struct Capture<'a, T> {
+    index: usize,
+    value: Option<&'a str>,
+    type_name: &'static str,
+    ty: PhantomData<T>,
+}
+
+impl<'a, T> Capture<'a, T> {
+    pub fn new(index: usize, value: Option<&'a str>, type_name: &'static str) -> Capture<'a, T> {
+        Capture {
+            index,
+            value,
+            type_name,
+            ty: PhantomData,
+        }
+    }
+
+    fn parse<U: FromStr>(&self, value: &str) -> U {
+        value.parse::<U>().unwrap_or_else(|_| {
+            panic!(
+                "Failed to parse {}th argument '{}' to type {}",
+                self.index, value, self.type_name
+            )
+        })
+    }
+}
+
+pub trait ParseOptional<T> {
+    fn parse_capture(&self) -> T;
+}
+
+impl<'a, T: FromStr> ParseOptional<Option<T>> for Capture<'a, Option<T>> {
+    /// A capture group that did not participate in the match is `None`.
+    fn parse_capture(&self) -> Option<T> {
+        self.value.map(|value| self.parse(value))
+    }
+}
+
+pub trait ParseRequired<T> {
+    fn parse_capture(&self) -> T;
+}
+
+impl<'a, 'b, T: FromStr> ParseRequired<T> for &'b Capture<'a, T> {
+    /// A capture group that did not participate in the match is parsed as an
+    /// empty string.
+    fn parse_capture(&self) -> T {
+        self.parse(self.value.unwrap_or_default())
+    }
+}
+
+/// Deserializes `T` from the named capture groups in `captures`.
+///
+/// Each field of `T` is looked up by name; groups that did not participate in
+/// the match are left out, so that `Option` fields become `None`.
+pub fn deserialize_captures<'de, T: Deserialize<'de>>(
+    captures: &Captures<'de>,
+    type_name: &'static str,
+) -> T {
+    T::deserialize(CapturesDeserializer(captures)).unwrap_or_else(|e| {
+        panic!(
+            "Failed to deserialize captures to type {}: {}",
+            type_name, e
+        )
+    })
+}
+
+struct CapturesDeserializer<'a, 'de>(&'a Captures<'de>);
+
+impl<'a, 'de> Deserializer<'de> for CapturesDeserializer<'a, 'de> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Error> {
+        Err(de::Error::custom(
+            "only structs can be deserialized from named captures",
+        ))
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        let captures = self.0;
+        let values = fields.iter().filter_map(|field| {
+            captures
+                .name(field)
+                .map(|match_| (*field, ValueDeserializer(match_.as_str())))
+        });
+
+        visitor.visit_map(MapDeserializer::new(values))
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct newtype_struct seq tuple
+        tuple_struct map enum identifier ignored_any
+    }
+}
+
+/// Deserializes a single string value, parsing it into numbers and the like
+/// as needed.
+pub(crate) struct ValueDeserializer<'de>(pub &'de str);
+
+impl<'de> IntoDeserializer<'de> for ValueDeserializer<'de> {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+macro_rules! deserialize_from_str {
+    ($($method:ident => $visit:ident,)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+                match self.0.parse() {
+                    Ok(v) => visitor.$visit(v),
+                    Err(_) => Err(de::Error::invalid_value(Unexpected::Str(self.0), &visitor)),
+                }
+            }
+        )*
+    };
+}
+
+impl<'de> Deserializer<'de> for ValueDeserializer<'de> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_borrowed_str(self.0)
+    }
+
+    deserialize_from_str! {
+        deserialize_bool => visit_bool,
+        deserialize_i8 => visit_i8,
+        deserialize_i16 => visit_i16,
+        deserialize_i32 => visit_i32,
+        deserialize_i64 => visit_i64,
+        deserialize_i128 => visit_i128,
+        deserialize_u8 => visit_u8,
+        deserialize_u16 => visit_u16,
+        deserialize_u32 => visit_u32,
+        deserialize_u64 => visit_u64,
+        deserialize_u128 => visit_u128,
+        deserialize_f32 => visit_f32,
+        deserialize_f64 => visit_f64,
+        deserialize_char => visit_char,
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_enum(self.0.into_deserializer())
+    }
+
+    forward_to_deserialize_any! {
+        str string bytes byte_buf unit unit_struct seq tuple tuple_struct map
+        struct identifier ignored_any
+    }
+}
@@ -1,9 +1,224 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
 use clap::{App, Arg};
 use regex::Regex;
 
+use crate::isolation;
+use crate::output::{ColorMode, Verbosity};
+use crate::shard;
+use crate::shuffle;
+use crate::table;
+
 #[derive(Debug)]
 pub enum CliError {
     InvalidFilterRegex,
+    UnknownFormat(String),
+    /// More than one of the formats given would write to stdout.
+    MultipleStdoutFormats,
+    /// The value of an option that takes a number was not one.
+    InvalidCount(&'static str, String),
+    /// A scenario location was not of the form `path[:line]`, its file does
+    /// not exist, or its line is not in the file.
+    InvalidLocation(String),
+    /// A file of scenario locations given as `@path` could not be read.
+    UnreadableRerunFile(PathBuf, io::Error),
+    /// The order was neither `defined` nor `random[:seed]`.
+    InvalidOrder(String),
+    /// The shard was not of the form `i/n`, with `i` from 1 to `n`.
+    InvalidShard(String),
+    /// The report to balance shards with could not be read.
+    UnreadableShardReport(PathBuf, String),
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CliError::InvalidFilterRegex => write!(f, "the expression is not a valid regex"),
+            CliError::UnknownFormat(format) => write!(f, "unknown output format `{}`", format),
+            CliError::MultipleStdoutFormats => write!(
+                f,
+                "only one output format can write to stdout; give the others a path"
+            ),
+            CliError::InvalidCount(option, value) => {
+                write!(f, "`{}` is not a valid count for --{}", value, option)
+            }
+            CliError::InvalidLocation(location) => write!(
+                f,
+                "`{}` is not a valid scenario location; expected path[:line]",
+                location
+            ),
+            CliError::UnreadableRerunFile(path, e) => {
+                write!(f, "Failed to read {}: {}", path.display(), e)
+            }
+            CliError::InvalidOrder(order) => write!(
+                f,
+                "`{}` is not a valid order; expected defined or random[:seed]",
+                order
+            ),
+            CliError::InvalidShard(shard) => write!(
+                f,
+                "`{}` is not a valid shard; expected i/n, with i from 1 to n",
+                shard
+            ),
+            CliError::UnreadableShardReport(path, e) => {
+                write!(f, "Failed to read {}: {}", path.display(), e)
+            }
+        }
+    }
+}
+
+impl std::error::Error for CliError {}
+
+/// An output format, given as `name[:path]` on the command line.
+///
+/// The name is that of a registered output, such as `pretty`, `json`, `html`,
+/// `junit` or `message`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutputFormat {
+    pub name: String,
+    /// The file to write to, or `None` for stdout.
+    pub path: Option<PathBuf>,
+}
+
+impl OutputFormat {
+    fn parse(s: &str) -> Result<OutputFormat, CliError> {
+        let mut chunks = s.splitn(2, ':');
+        let name = chunks.next().unwrap_or_default();
+        let path = chunks.next().map(PathBuf::from);
+
+        if name.is_empty() {
+            return Err(CliError::UnknownFormat(s.to_string()));
+        }
+
+        Ok(OutputFormat {
+            name: name.to_string(),
+            path,
+        })
+    }
+}
+
+/// Scenarios of a feature file, selected by the line they are on.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Location {
+    /// The canonical path of the feature file.
+    pub path: PathBuf,
+    /// The lines of the scenarios, or none for all of them.
+    pub lines: Vec<usize>,
+}
+
+impl Location {
+    /// Parses `path[:line[:column]]`, ignoring the column so that locations
+    /// can be pasted from the terminal output.
+    fn parse(s: &str) -> Result<Location, CliError> {
+        let invalid = || CliError::InvalidLocation(s.to_string());
+        let mut path = s;
+        let mut numbers = vec![];
+        while numbers.len() < 2 {
+            match path.rsplit_once(':').map(|(rest, n)| (rest, n.parse())) {
+                Some((rest, Ok(n))) => {
+                    numbers.push(n);
+                    path = rest;
+                }
+                _ => break,
+            }
+        }
+        // The line comes before the column, so was parsed last.
+        let lines: Vec<usize> = numbers.last().copied().into_iter().collect();
+
+        let path = Path::new(path).canonicalize().map_err(|_| invalid())?;
+
+        // A line outside the file would select the feature's header, or the
+        // scenario before it, rather than nothing.
+        if let Some(&line) = lines.first() {
+            let contents = fs::read_to_string(&path).map_err(|_| invalid())?;
+            if line == 0 || line > contents.lines().count() {
+                return Err(invalid());
+            }
+        }
+
+        Ok(Location { path, lines })
+    }
+
+    /// Adds `location` to `locations`, merging the lines of locations in the
+    /// same file.
+    fn merge_into(self, locations: &mut Vec<Location>) {
+        match locations.iter_mut().find(|l| l.path == self.path) {
+            Some(existing) if existing.lines.is_empty() => {}
+            Some(existing) if self.lines.is_empty() => existing.lines.clear(),
+            Some(existing) => existing.lines.extend(self.lines),
+            None => locations.push(self),
+        }
+    }
+
+    /// Reads the locations in a rerun file, separated by whitespace.
+    fn read_rerun_file(path: &Path) -> Result<Vec<Location>, CliError> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| CliError::UnreadableRerunFile(path.to_path_buf(), e))?;
+        contents.split_whitespace().map(Location::parse).collect()
+    }
+}
+
+/// The order features and scenarios are run in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Order {
+    /// Features sorted by path, and scenarios in the order they are written.
+    #[default]
+    Defined,
+    /// Features, rules and scenarios shuffled with a seed, which gives the
+    /// same order when used again.
+    Random(u64),
+}
+
+impl Order {
+    /// Parses `defined` or `random[:seed]`, choosing a seed if none is given.
+    fn parse(s: &str) -> Result<Order, CliError> {
+        match s.split_once(':') {
+            None if s == "defined" => Ok(Order::Defined),
+            None if s == "random" => Ok(Order::Random(shuffle::random_seed())),
+            Some(("random", seed)) => seed
+                .parse()
+                .map(Order::Random)
+                .map_err(|_| CliError::InvalidOrder(s.to_string())),
+            _ => Err(CliError::InvalidOrder(s.to_string())),
+        }
+    }
+}
+
+/// One of several shards the scenarios are split into, to run on separate
+/// machines.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Shard {
+    /// Which shard this is, from 1 to `count`.
+    pub index: usize,
+    pub count: usize,
+    /// The durations of scenarios in a previous run, by `path:line`, to
+    /// balance the shards with.
+    pub durations: HashMap<String, Duration>,
+}
+
+impl Shard {
+    /// Parses `i/n`.
+    fn parse(s: &str) -> Result<Shard, CliError> {
+        let invalid = || CliError::InvalidShard(s.to_string());
+        let (index, count) = s.split_once('/').ok_or_else(invalid)?;
+        let index = index.parse().map_err(|_| invalid())?;
+        let count = count.parse().map_err(|_| invalid())?;
+
+        if index == 0 || index > count {
+            return Err(invalid());
+        }
+
+        Ok(Shard {
+            index,
+            count,
+            durations: HashMap::new(),
+        })
+    }
 }
 
 pub struct CliOptions {
@@ -11,6 +226,114 @@ pub struct CliOptions {
     pub filter: Option<Regex>,
     pub tag: Option<String>,
     pub suppress_output: bool,
+    /// The output formats, of which at most one writes to stdout.
+    pub formats: Vec<OutputFormat>,
+    pub verbosity: Verbosity,
+    pub color: ColorMode,
+    /// How many of the slowest scenarios, steps and step definitions to
+    /// report at the end of the run.
+    pub report_slowest: Option<usize>,
+    /// The width to wrap the terminal output at, with `Some(0)` for no
+    /// wrapping, or `None` to use the width of the terminal.
+    pub width: Option<usize>,
+    /// The scenarios to run, or `None` for all of them.
+    pub locations: Option<Vec<Location>>,
+    pub order: Order,
+    /// The shard of the scenarios to run, or `None` for all of them.
+    pub shard: Option<Shard>,
+    /// How many scenarios to run in each child process, or `None` to run them
+    /// all in this one.
+    pub isolate: Option<usize>,
+    /// Whether this is a child process running scenarios for `--isolate`.
+    pub is_isolated_child: bool,
+}
+
+impl CliOptions {
+    /// Whether a report other than the terminal output is written to stdout,
+    /// so that anything else for the terminal must go to stderr.
+    pub fn stdout_is_report(&self) -> bool {
+        self.formats
+            .iter()
+            .any(|format| format.path.is_none() && format.name != "pretty")
+    }
+
+    /// Whether `expanded`, one of the scenarios `scenario` of the feature
+    /// file at `path` expands to, is to be run.
+    pub fn is_selected(
+        &self,
+        path: &Path,
+        feature: &gherkin::Feature,
+        rule: Option<&gherkin::Rule>,
+        scenario: &gherkin::Scenario,
+        expanded: &gherkin::Scenario,
+    ) -> bool {
+        let locations = match &self.locations {
+            Some(locations) => locations,
+            None => return true,
+        };
+        let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+
+        locations.iter().any(|location| {
+            location.path == path
+                && (location.lines.is_empty()
+                    || location
+                        .lines
+                        .iter()
+                        .any(|&line| covers(line, feature, rule, scenario, expanded)))
+        })
+    }
+}
+
+/// Whether `line` is within `expanded`, or the Examples row, scenario, rule or
+/// feature header it was expanded from.
+fn covers(
+    line: usize,
+    feature: &gherkin::Feature,
+    rule: Option<&gherkin::Rule>,
+    scenario: &gherkin::Scenario,
+    expanded: &gherkin::Scenario,
+) -> bool {
+    // Everything up to the next scenario or rule belongs to the one before.
+    let starts = feature
+        .scenarios
+        .iter()
+        .map(|s| s.position.0)
+        .chain(feature.rules.iter().flat_map(|r| {
+            std::iter::once(r.position.0).chain(r.scenarios.iter().map(|s| s.position.0))
+        }))
+        .collect::<Vec<_>>();
+    let next_start = |after: usize| {
+        starts
+            .iter()
+            .copied()
+            .filter(|&start| start > after)
+            .min()
+            .unwrap_or(usize::MAX)
+    };
+
+    // The feature's header and background, or a rule's.
+    if line < starts.iter().copied().min().unwrap_or(usize::MAX) {
+        return true;
+    }
+    if let Some(rule) = rule {
+        if line >= rule.position.0 && line < next_start(rule.position.0) {
+            return true;
+        }
+    }
+
+    if line < scenario.position.0 || line >= next_start(scenario.position.0) {
+        return false;
+    }
+
+    // A row of examples selects only the scenario expanded from it.
+    match &scenario.examples {
+        Some(examples) => {
+            let rows = examples.table.rows.len();
+            let is_row = (0..rows).any(|n| table::row_line(&examples.table, n) == line);
+            !is_row || expanded.position.0 == line
+        }
+        None => true,
+    }
 }
 
 pub fn make_app() -> Result<CliOptions, CliError> {
@@ -42,11 +365,107 @@ pub fn make_app() -> Result<CliOptions, CliError> {
                 .help("Filter by specified tag")
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("format")
+                .long("format")
+                .value_name("name[:path]")
+                .help(
+                    "Output format: pretty, json, html, junit, message, rerun or a registered output, with an optional path to write to, which rerun requires. May be repeated",
+                )
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("verbose")
+                .short("v")
+                .long("verbose")
+                .help("Report everything, including details normally left out")
+                .conflicts_with("quiet"),
+        )
+        .arg(
+            Arg::with_name("quiet")
+                .short("q")
+                .long("quiet")
+                .help("Report only what went wrong, and the summary"),
+        )
+        .arg(
+            Arg::with_name("color")
+                .long("color")
+                .value_name("when")
+                .help("Whether to color the terminal output")
+                .possible_values(&["auto", "always", "never"])
+                .default_value("auto")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("report-slowest")
+                .long("report-slowest")
+                .value_name("N")
+                .help("Report the N slowest scenarios, steps and step definitions at the end")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("width")
+                .long("width")
+                .value_name("columns")
+                .help("Wrap the terminal output at this width, or 0 not to wrap it. Defaults to the width of the terminal")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("order")
+                .long("order")
+                .value_name("defined|random[:seed]")
+                .help("Run features and scenarios in the order they are defined, or shuffled. Give the seed printed by a shuffled run to repeat its order")
+                .default_value("defined")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("shard")
+                .long("shard")
+                .value_name("i/n")
+                .help("Split the scenarios into n shards and run only the i-th, counting from 1")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("shard-durations")
+                .long("shard-durations")
+                .value_name("report.json")
+                .help("Balance the shards by the durations of scenarios in a previous JSON report")
+                .requires("shard")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("isolate")
+                .long("isolate")
+                .help("Run each scenario in a child process, so that one that aborts or crashes is reported as failed instead of ending the run"),
+        )
+        .arg(
+            Arg::with_name("isolate-batch")
+                .long("isolate-batch")
+                .value_name("N")
+                .help("Run N scenarios in each child process when isolating them")
+                .requires("isolate")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("rerun-file")
+                .long("rerun-file")
+                .value_name("path")
+                .help("Write the locations of failed scenarios to a file, to run them again with @path")
+                .takes_value(true),
+        )
         .arg(
             Arg::with_name("nocapture")
                 .long("nocapture")
                 .help("Use this flag to disable suppression of output from tests"),
         )
+        .arg(
+            Arg::with_name("locations")
+                .value_name("path:line or @rerun-file")
+                .help("Run only the scenario, Examples row or rule at a line of a feature file, or those listed in a file written by --rerun-file. May be repeated")
+                .multiple(true),
+        )
         .get_matches();
 
     let filter = if let Some(filter) = matches.value_of("filter") {
@@ -61,10 +480,334 @@ pub fn make_app() -> Result<CliOptions, CliError> {
 
     let suppress_output = !matches.is_present("nocapture");
 
-    Ok(CliOptions {
+    let mut formats = match matches.values_of("format") {
+        Some(formats) => formats
+            .map(OutputFormat::parse)
+            .collect::<Result<Vec<_>, _>>()?,
+        None => vec![OutputFormat {
+            name: "pretty".to_string(),
+            path: None,
+        }],
+    };
+
+    if formats.iter().filter(|f| f.path.is_none()).count() > 1 {
+        return Err(CliError::MultipleStdoutFormats);
+    }
+
+    // `--rerun-file <path>` is short for `--format rerun:<path>`, alongside
+    // the terminal output.
+    if let Some(path) = matches.value_of("rerun-file") {
+        formats.push(OutputFormat {
+            name: "rerun".to_string(),
+            path: Some(PathBuf::from(path)),
+        });
+    }
+
+    let verbosity = if matches.is_present("verbose") {
+        Verbosity::Verbose
+    } else if matches.is_present("quiet") {
+        Verbosity::Quiet
+    } else {
+        Verbosity::Normal
+    };
+
+    let color = match matches.value_of("color") {
+        Some("always") => ColorMode::Always,
+        Some("never") => ColorMode::Never,
+        _ => ColorMode::Auto,
+    };
+
+    let report_slowest = match matches.value_of("report-slowest") {
+        Some(n) => Some(
+            n.parse()
+                .map_err(|_| CliError::InvalidCount("report-slowest", n.to_string()))?,
+        ),
+        None => None,
+    };
+
+    let width = match matches.value_of("width") {
+        Some(n) => Some(
+            n.parse()
+                .map_err(|_| CliError::InvalidCount("width", n.to_string()))?,
+        ),
+        None => None,
+    };
+
+    let locations = match matches.values_of("locations") {
+        Some(values) => {
+            let mut locations = vec![];
+            for value in values {
+                let parsed = match value.strip_prefix('@') {
+                    Some(path) => Location::read_rerun_file(Path::new(path))?,
+                    None => vec![Location::parse(value)?],
+                };
+                for location in parsed {
+                    location.merge_into(&mut locations);
+                }
+            }
+            Some(locations)
+        }
+        None => None,
+    };
+
+    let order = Order::parse(matches.value_of("order").unwrap_or("defined"))?;
+
+    let shard = match matches.value_of("shard") {
+        Some(shard) => {
+            let mut shard = Shard::parse(shard)?;
+            if let Some(path) = matches.value_of("shard-durations") {
+                shard.durations = shard::read_durations(Path::new(path))
+                    .map_err(|e| CliError::UnreadableShardReport(PathBuf::from(path), e))?;
+            }
+            Some(shard)
+        }
+        None => None,
+    };
+
+    let isolate = match matches.value_of("isolate-batch") {
+        Some(n) => match n.parse() {
+            Ok(n) if n > 0 => Some(n),
+            _ => return Err(CliError::InvalidCount("isolate-batch", n.to_string())),
+        },
+        None if matches.is_present("isolate") => Some(1),
+        None => None,
+    };
+
+    let mut options = CliOptions {
         feature,
         filter,
         tag,
         suppress_output,
-    })
+        formats,
+        verbosity,
+        color,
+        report_slowest,
+        width,
+        locations,
+        order,
+        shard,
+        isolate,
+        is_isolated_child: false,
+    };
+
+    // A child process runs the scenarios it was given, as they were selected
+    // and ordered by its parent, and reports them only to its parent.
+    if let Ok(scenarios) = std::env::var(isolation::SCENARIOS_VAR) {
+        let locations = scenarios
+            .lines()
+            .map(Location::parse)
+            .collect::<Result<Vec<_>, _>>()?;
+        options.locations = Some(locations);
+        options.order = Order::Defined;
+        options.shard = None;
+        options.isolate = None;
+        options.report_slowest = None;
+        options.is_isolated_child = true;
+    }
+
+    Ok(options)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use gherkin::Feature;
+
+    use super::{CliError, Location};
+    use crate::outline;
+    use crate::testing;
+
+    const SOURCE: &str = "\
+Feature: Selecting
+
+  Background:
+    Given a step that passes
+
+  Scenario: first
+    Given a step that passes
+
+  Scenario Outline: outline
+    Given a step that <does>
+
+    Examples:
+      | does   |
+      | passes |
+      | fails  |
+
+  Rule: A rule
+
+    Scenario: in a rule
+      Given a step that passes
+";
+
+    fn location(path: &Path, lines: &[usize]) -> Location {
+        Location {
+            path: path.canonicalize().unwrap(),
+            lines: lines.to_vec(),
+        }
+    }
+
+    /// The names of the scenarios selected by `lines` of the feature `name`.
+    fn selected(name: &str, lines: &[usize]) -> Vec<String> {
+        let path = testing::feature_file(name, SOURCE);
+        let feature = Feature::try_from(SOURCE).unwrap();
+        let mut options = testing::options();
+        options.locations = Some(vec![location(&path, lines)]);
+
+        let scenarios = feature.scenarios.iter().map(|s| (None, s));
+        let in_rules = feature
+            .rules
+            .iter()
+            .flat_map(|r| r.scenarios.iter().map(move |s| (Some(r), s)));
+        scenarios
+            .chain(in_rules)
+            .flat_map(|(rule, scenario)| {
+                outline::expand(scenario)
+                    .into_iter()
+                    .filter(|expanded| {
+                        options.is_selected(&path, &feature, rule, scenario, expanded)
+                    })
+                    .map(|expanded| expanded.name)
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn parses_a_path_with_or_without_a_line() {
+        let path = testing::feature_file("cli-parse", SOURCE);
+        let file = "target/test-features/cli-parse.feature";
+
+        assert_eq!(Location::parse(file).unwrap(), location(&path, &[]));
+        assert_eq!(
+            Location::parse(&format!("{}:6", file)).unwrap(),
+            location(&path, &[6])
+        );
+    }
+
+    #[test]
+    fn ignores_the_column_after_a_line() {
+        let path = testing::feature_file("cli-column", SOURCE);
+        let file = "target/test-features/cli-column.feature";
+
+        assert_eq!(
+            Location::parse(&format!("{}:6:3", file)).unwrap(),
+            location(&path, &[6])
+        );
+    }
+
+    #[test]
+    fn keeps_the_colons_of_an_absolute_path() {
+        // On Windows, this starts with a drive such as `C:\`.
+        let path = testing::feature_file("cli-absolute", SOURCE);
+        let absolute = path.canonicalize().unwrap();
+
+        assert_eq!(
+            Location::parse(&format!("{}:6", absolute.display())).unwrap(),
+            location(&path, &[6])
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn keeps_colons_in_file_names() {
+        let path = testing::feature_file("cli:colon", SOURCE);
+        let file = "target/test-features/cli:colon.feature";
+
+        assert_eq!(
+            Location::parse(&format!("{}:6", file)).unwrap(),
+            location(&path, &[6])
+        );
+    }
+
+    #[test]
+    fn rejects_missing_files_and_lines_outside_the_file() {
+        testing::feature_file("cli-lines", SOURCE);
+        let file = "target/test-features/cli-lines.feature";
+
+        for s in &[
+            "target/test-features/missing.feature:6".to_string(),
+            format!("{}:0", file),
+            format!("{}:21", file),
+        ] {
+            match Location::parse(s) {
+                Err(CliError::InvalidLocation(location)) => assert_eq!(&location, s),
+                result => panic!("unexpected {:?} for {}", result, s),
+            }
+        }
+        assert!(Location::parse(&format!("{}:20", file)).is_ok());
+    }
+
+    #[test]
+    fn merges_the_lines_of_locations_in_the_same_file() {
+        let a = testing::feature_file("cli-merge-a", SOURCE);
+        let b = testing::feature_file("cli-merge-b", SOURCE);
+
+        let mut locations = vec![];
+        location(&a, &[6]).merge_into(&mut locations);
+        location(&b, &[9]).merge_into(&mut locations);
+        location(&a, &[19]).merge_into(&mut locations);
+        assert_eq!(locations, vec![location(&a, &[6, 19]), location(&b, &[9])]);
+
+        // A whole file takes in any of its lines, before or after it.
+        location(&a, &[]).merge_into(&mut locations);
+        location(&b, &[]).merge_into(&mut locations);
+        location(&b, &[6]).merge_into(&mut locations);
+        assert_eq!(locations, vec![location(&a, &[]), location(&b, &[])]);
+    }
+
+    #[test]
+    fn reads_rerun_files_with_blank_lines() {
+        let path = testing::feature_file("cli-rerun", SOURCE);
+        let file = "target/test-features/cli-rerun.feature";
+        let rerun =
+            PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("target/test-features/cli.rerun");
+        std::fs::write(&rerun, format!("\n{0}:6\n\n  {0}:14 \n\n", file)).unwrap();
+
+        assert_eq!(
+            Location::read_rerun_file(&rerun).unwrap(),
+            vec![location(&path, &[6]), location(&path, &[14])]
+        );
+
+        let missing = rerun.with_extension("missing");
+        match Location::read_rerun_file(&missing) {
+            Err(CliError::UnreadableRerunFile(path, _)) => assert_eq!(path, missing),
+            result => panic!("unexpected {:?}", result),
+        }
+    }
+
+    #[test]
+    fn selects_scenarios_by_any_of_their_lines() {
+        let all = vec![
+            "first",
+            "outline (example 1)",
+            "outline (example 2)",
+            "in a rule",
+        ];
+
+        assert_eq!(selected("cli-none", &[]), all);
+        // The feature's header and background belong to every scenario.
+        assert_eq!(selected("cli-header", &[1]), all);
+        assert_eq!(selected("cli-background", &[4]), all);
+
+        assert_eq!(selected("cli-scenario", &[6]), vec!["first"]);
+        assert_eq!(selected("cli-step", &[7]), vec!["first"]);
+        assert_eq!(selected("cli-blank", &[8]), vec!["first"]);
+        assert_eq!(
+            selected("cli-outline", &[9]),
+            vec!["outline (example 1)", "outline (example 2)"]
+        );
+        assert_eq!(selected("cli-row", &[15]), vec!["outline (example 2)"]);
+        assert_eq!(
+            selected("cli-examples-header", &[13]),
+            vec!["outline (example 1)", "outline (example 2)"]
+        );
+        assert_eq!(selected("cli-rule", &[17]), vec!["in a rule"]);
+        assert_eq!(selected("cli-in-rule", &[20]), vec!["in a rule"]);
+        assert_eq!(
+            selected("cli-several", &[6, 14]),
+            vec!["first", "outline (example 1)"]
+        );
+    }
 }
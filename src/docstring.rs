@@ -0,0 +1,231 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Mutex;
+
+use gherkin::{Feature, Step};
+use serde::de::DeserializeOwned;
+
+/// The opening quotes of each docstring in a feature, keyed by line, with the
+/// content type that followed them, if any.
+pub(crate) type Openers = BTreeMap<usize, Option<String>>;
+
+// The line of the opener of each docstring loaded, and its content type, keyed
+// by the position and docstring of its step. A step is looked up by itself
+// alone, so that it is found whichever feature it is in, and from any thread.
+type StepKey = (usize, usize, String);
+
+static STEP_OPENERS: Mutex<BTreeMap<StepKey, (usize, Option<String>)>> =
+    Mutex::new(BTreeMap::new());
+
+fn step_key(step: &Step) -> Option<StepKey> {
+    let docstring = step.docstring.as_ref()?;
+    Some((step.position.0, step.position.1, docstring.clone()))
+}
+
+/// Removes the content types from docstring openers such as `"""json`, which
+/// the Gherkin parser does not understand, returning the rewritten source and
+/// the openers found. Docstrings between backticks, as in ```` ```json ````,
+/// are rewritten to be between quotes.
+pub(crate) fn strip_content_types(source: &str) -> (String, Openers) {
+    let mut openers = Openers::new();
+    let mut delimiter: Option<&str> = None;
+
+    let lines = source
+        .lines()
+        .enumerate()
+        .map(|(n, line)| {
+            let trimmed = line.trim_start();
+            let indent = &line[..line.len() - trimmed.len()];
+            let quotes = match delimiter {
+                Some(delimiter) if trimmed.starts_with(delimiter) => delimiter,
+                Some(_) => return line.to_string(),
+                None if trimmed.starts_with("\"\"\"") => "\"\"\"",
+                None if trimmed.starts_with("```") => "```",
+                None => return line.to_string(),
+            };
+
+            if delimiter.take().is_some() {
+                return format!("{}\"\"\"{}", indent, &trimmed[3..]);
+            }
+
+            delimiter = Some(quotes);
+            let content_type = trimmed[3..].trim();
+            if content_type.is_empty() {
+                openers.insert(n + 1, None);
+            } else {
+                openers.insert(n + 1, Some(content_type.to_string()));
+            }
+            format!("{}\"\"\"", indent)
+        })
+        .collect::<Vec<_>>();
+
+    let mut source = lines.join("\n");
+    if !source.is_empty() {
+        source.push('\n');
+    }
+
+    (source, openers)
+}
+
+/// Keeps the openers of the docstrings of the steps of `feature`, for
+/// `DocstringExt` to find by step. A docstring opens on the first opener
+/// following its step, which is not necessarily on the next line.
+pub(crate) fn keep_content_types(feature: &Feature, openers: &Openers) {
+    let steps = feature
+        .background
+        .iter()
+        .flat_map(|background| background.steps.iter())
+        .chain(
+            feature
+                .scenarios
+                .iter()
+                .chain(feature.rules.iter().flat_map(|rule| rule.scenarios.iter()))
+                .flat_map(|scenario| scenario.steps.iter()),
+        );
+
+    let mut step_openers = STEP_OPENERS.lock().unwrap_or_else(|e| e.into_inner());
+    for step in steps {
+        let key = match step_key(step) {
+            Some(key) => key,
+            None => continue,
+        };
+        if let Some((line, content_type)) = openers.range(step.position.0 + 1..).next() {
+            step_openers.insert(key, (*line, content_type.clone()));
+        }
+    }
+}
+
+/// Keeps the opener of the docstring of `step` for `expanded`, the same step
+/// with the placeholders of a scenario outline filled in.
+pub(crate) fn keep_content_type_of_expanded(step: &Step, expanded: &Step) {
+    if let (Some(opener), Some(key)) = (opener(step), step_key(expanded)) {
+        STEP_OPENERS
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(key, opener);
+    }
+}
+
+/// The line and content type of the opener of `step`'s docstring.
+fn opener(step: &Step) -> Option<(usize, Option<String>)> {
+    let key = step_key(step)?;
+    STEP_OPENERS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .get(&key)
+        .cloned()
+}
+
+/// An error parsing a docstring, with the line of the .feature file it
+/// occurred on.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DocstringError {
+    /// The line in the .feature file.
+    pub line: usize,
+    /// A description of what went wrong.
+    pub message: String,
+}
+
+impl fmt::Display for DocstringError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for DocstringError {}
+
+/// Access to the content type of a step's docstring, and parsing of the
+/// docstring by content type.
+///
+/// The content type is the word following the opening quotes, as in `"""json`.
+pub trait DocstringExt {
+    /// The content type of the docstring, if one was given.
+    fn docstring_content_type(&self) -> Option<String>;
+
+    /// Parses the docstring according to its content type, which must be one
+    /// of those supported by the `docstring_*` methods.
+    fn parse_docstring<T: DeserializeOwned>(&self) -> Result<T, DocstringError>;
+
+    /// Parses the docstring as JSON.
+    fn docstring_json<T: DeserializeOwned>(&self) -> Result<T, DocstringError>;
+
+    /// Parses the docstring as YAML.
+    #[cfg(feature = "yaml")]
+    fn docstring_yaml<T: DeserializeOwned>(&self) -> Result<T, DocstringError>;
+
+    /// Parses the docstring as TOML.
+    #[cfg(feature = "toml")]
+    fn docstring_toml<T: DeserializeOwned>(&self) -> Result<T, DocstringError>;
+}
+
+impl DocstringExt for Step {
+    fn docstring_content_type(&self) -> Option<String> {
+        opener(self).and_then(|(_, content_type)| content_type)
+    }
+
+    fn parse_docstring<T: DeserializeOwned>(&self) -> Result<T, DocstringError> {
+        let content_type = self.docstring_content_type();
+
+        match content_type.as_deref() {
+            Some("json") | Some("application/json") => self.docstring_json(),
+            #[cfg(feature = "yaml")]
+            Some("yaml") | Some("yml") | Some("application/yaml") => self.docstring_yaml(),
+            #[cfg(feature = "toml")]
+            Some("toml") | Some("application/toml") => self.docstring_toml(),
+            Some(other) => {
+                Err(self.docstring_error(0, format!("unsupported content type `{}`", other)))
+            }
+            None => Err(self.docstring_error(0, "docstring has no content type".to_string())),
+        }
+    }
+
+    fn docstring_json<T: DeserializeOwned>(&self) -> Result<T, DocstringError> {
+        serde_json::from_str(self.docstring_content()?)
+            .map_err(|e| self.docstring_error(e.line(), e.to_string()))
+    }
+
+    #[cfg(feature = "yaml")]
+    fn docstring_yaml<T: DeserializeOwned>(&self) -> Result<T, DocstringError> {
+        serde_yaml::from_str(self.docstring_content()?).map_err(|e| {
+            let line = e.location().map(|l| l.line()).unwrap_or(0);
+            self.docstring_error(line, e.to_string())
+        })
+    }
+
+    #[cfg(feature = "toml")]
+    fn docstring_toml<T: DeserializeOwned>(&self) -> Result<T, DocstringError> {
+        toml::from_str(self.docstring_content()?).map_err(|e| {
+            let line = e.line_col().map(|(line, _)| line + 1).unwrap_or(0);
+            self.docstring_error(line, e.to_string())
+        })
+    }
+}
+
+trait DocstringContent {
+    fn docstring_content(&self) -> Result<&str, DocstringError>;
+    fn docstring_error(&self, line: usize, message: String) -> DocstringError;
+}
+
+impl DocstringContent for Step {
+    fn docstring_content(&self) -> Result<&str, DocstringError> {
+        self.docstring
+            .as_deref()
+            .ok_or_else(|| self.docstring_error(0, "step has no docstring".to_string()))
+    }
+
+    /// `line` is the line within the docstring, or 0 for the docstring itself.
+    fn docstring_error(&self, line: usize, message: String) -> DocstringError {
+        DocstringError {
+            line: opener_line(self) + line,
+            message,
+        }
+    }
+}
+
+// The line of the opening quotes of the docstring of `step`, assumed to be the
+// one following it if its feature was not loaded.
+fn opener_line(step: &Step) -> usize {
+    opener(step)
+        .map(|(line, _)| line)
+        .unwrap_or(step.position.0 + 1)
+}
@@ -0,0 +1,503 @@
+use std::env;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::Path;
+use std::process::{Command, ExitStatus, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde_json::{json, Value};
+
+use crate::output::relpath;
+use crate::{HookType, LogRecord, OutputVisitor, PanicDetails, TestResult};
+
+/// Set in the environment of a child process to the `path:line` locations of
+/// the scenarios it is to run, one per line.
+pub(crate) const SCENARIOS_VAR: &str = "CUCUMBER_ISOLATED_SCENARIOS";
+
+// Starts the lines of stdout that are events, rather than output of the steps.
+const EVENT_PREFIX: &str = "\u{1e}cucumber-event:";
+
+/// The output of a child process, which writes each event to stdout for the
+/// parent to report.
+pub(crate) struct ChildOutput;
+
+impl ChildOutput {
+    fn emit(&self, event: Value) {
+        // Flushed at once, so that the parent has every event up to a crash.
+        let mut stdout = io::stdout().lock();
+        writeln!(stdout, "{}{}", EVENT_PREFIX, event).expect("failed to write event");
+        stdout.flush().expect("failed to write event");
+    }
+}
+
+fn nanos(duration: Duration) -> u64 {
+    duration.as_nanos() as u64
+}
+
+fn encode_result(result: &TestResult) -> Value {
+    match result {
+        TestResult::Pass => json!({ "status": "passed" }),
+        TestResult::Fail(panic_info, stdout, stderr) => json!({
+            "status": "failed",
+            "payload": panic_info.payload,
+            "location": panic_info.location,
+            "stdout": String::from_utf8_lossy(stdout),
+            "stderr": String::from_utf8_lossy(stderr),
+        }),
+        TestResult::Skipped => json!({ "status": "skipped" }),
+        TestResult::Unimplemented => json!({ "status": "unimplemented" }),
+        TestResult::MutexPoisoned => json!({ "status": "poisoned" }),
+    }
+}
+
+fn decode_result(result: &Value) -> TestResult {
+    let text = |key: &str| result[key].as_str().unwrap_or_default().to_string();
+
+    match result["status"].as_str() {
+        Some("passed") => TestResult::Pass,
+        Some("failed") => TestResult::Fail(
+            PanicDetails {
+                payload: text("payload"),
+                location: text("location"),
+            },
+            text("stdout").into_bytes(),
+            text("stderr").into_bytes(),
+        ),
+        Some("unimplemented") => TestResult::Unimplemented,
+        Some("poisoned") => TestResult::MutexPoisoned,
+        _ => TestResult::Skipped,
+    }
+}
+
+impl OutputVisitor for ChildOutput {
+    fn visit_start(&mut self) {}
+
+    fn visit_feature(&mut self, _feature: &gherkin::Feature, _path: &Path) {}
+
+    fn visit_feature_end(&mut self, _feature: &gherkin::Feature, _duration: Duration) {}
+
+    fn visit_feature_error<'a>(&mut self, _path: &Path, _error: &gherkin::Error<'a>) {}
+
+    fn visit_rule(&mut self, _rule: &gherkin::Rule) {}
+
+    fn visit_rule_end(&mut self, _rule: &gherkin::Rule) {}
+
+    fn visit_scenario(&mut self, _rule: Option<&gherkin::Rule>, scenario: &gherkin::Scenario) {
+        self.emit(json!({ "event": "scenario", "line": scenario.position.0 }));
+    }
+
+    fn visit_scenario_end(
+        &mut self,
+        _rule: Option<&gherkin::Rule>,
+        scenario: &gherkin::Scenario,
+        duration: Duration,
+    ) {
+        self.emit(json!({
+            "event": "scenario_end",
+            "line": scenario.position.0,
+            "duration": nanos(duration),
+        }));
+    }
+
+    fn visit_hook(
+        &mut self,
+        _rule: Option<&gherkin::Rule>,
+        scenario: &gherkin::Scenario,
+        hook: HookType,
+        duration: Duration,
+    ) {
+        self.emit(json!({
+            "event": "hook",
+            "line": scenario.position.0,
+            "hook": if hook == HookType::Before { "before" } else { "after" },
+            "duration": nanos(duration),
+        }));
+    }
+
+    fn visit_scenario_skipped(
+        &mut self,
+        _rule: Option<&gherkin::Rule>,
+        scenario: &gherkin::Scenario,
+    ) {
+        self.emit(json!({ "event": "scenario_skipped", "line": scenario.position.0 }));
+    }
+
+    fn visit_step(
+        &mut self,
+        _rule: Option<&gherkin::Rule>,
+        scenario: &gherkin::Scenario,
+        step: &gherkin::Step,
+    ) {
+        self.emit(json!({
+            "event": "step",
+            "line": scenario.position.0,
+            "step": step.position.0,
+        }));
+    }
+
+    fn visit_logs(
+        &mut self,
+        _rule: Option<&gherkin::Rule>,
+        scenario: &gherkin::Scenario,
+        step: Option<&gherkin::Step>,
+        records: &[LogRecord],
+    ) {
+        let records = records
+            .iter()
+            .map(|record| {
+                json!({
+                    "level": record.level,
+                    "target": record.target,
+                    "message": record.message,
+                })
+            })
+            .collect::<Vec<_>>();
+
+        self.emit(json!({
+            "event": "logs",
+            "line": scenario.position.0,
+            "step": step.map(|step| step.position.0),
+            "records": records,
+        }));
+    }
+
+    fn visit_step_result(
+        &mut self,
+        _rule: Option<&gherkin::Rule>,
+        scenario: &gherkin::Scenario,
+        step: &gherkin::Step,
+        result: &TestResult,
+        duration: Duration,
+    ) {
+        self.emit(json!({
+            "event": "step_result",
+            "line": scenario.position.0,
+            "step": step.position.0,
+            "duration": nanos(duration),
+            "result": encode_result(result),
+        }));
+    }
+
+    fn visit_finish(&mut self, _duration: Duration) {
+        self.emit(json!({ "event": "finish" }));
+    }
+}
+
+// The scenario a child process is running, as reported so far.
+struct Running<'a> {
+    scenario: &'a gherkin::Scenario,
+    started: Instant,
+    // The steps of the background and scenario, and how many have a result.
+    steps: Vec<&'a gherkin::Step>,
+    finished_steps: usize,
+    // Whether the next step has been started.
+    is_in_step: bool,
+}
+
+impl<'a> Running<'a> {
+    fn new(feature: &'a gherkin::Feature, scenario: &'a gherkin::Scenario) -> Running<'a> {
+        Running {
+            scenario,
+            started: Instant::now(),
+            steps: feature
+                .background
+                .iter()
+                .flat_map(|bg| bg.steps.iter())
+                .chain(scenario.steps.iter())
+                .collect(),
+            finished_steps: 0,
+            is_in_step: false,
+        }
+    }
+
+    fn step(&self, event: &Value) -> Option<&'a gherkin::Step> {
+        let line = event["step"].as_u64()? as usize;
+        self.steps
+            .iter()
+            .copied()
+            .find(|step| step.position.0 == line)
+    }
+}
+
+/// Reports an event of the scenario being run, returning whether it was not a
+/// failure.
+fn replay(
+    event: &Value,
+    rule: Option<&gherkin::Rule>,
+    current: &mut Running,
+    output: &mut dyn OutputVisitor,
+) -> bool {
+    let scenario = current.scenario;
+    let duration = Duration::from_nanos(event["duration"].as_u64().unwrap_or_default());
+
+    match event["event"].as_str() {
+        Some("hook") => {
+            let hook = match event["hook"].as_str() {
+                Some("before") => HookType::Before,
+                _ => HookType::After,
+            };
+            output.visit_hook(rule, scenario, hook, duration);
+        }
+        Some("scenario_skipped") => output.visit_scenario_skipped(rule, scenario),
+        Some("logs") => {
+            let text =
+                |record: &Value, key: &str| record[key].as_str().unwrap_or_default().to_string();
+            let records = event["records"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .map(|record| LogRecord {
+                    level: text(record, "level"),
+                    target: text(record, "target"),
+                    message: text(record, "message"),
+                })
+                .collect::<Vec<_>>();
+            output.visit_logs(rule, scenario, current.step(event), &records);
+        }
+        Some("step") => {
+            if let Some(step) = current.step(event) {
+                output.visit_step(rule, scenario, step);
+                current.is_in_step = true;
+            }
+        }
+        Some("step_result") => {
+            if let Some(step) = current.step(event) {
+                let result = decode_result(&event["result"]);
+                output.visit_step_result(rule, scenario, step, &result, duration);
+                current.finished_steps += 1;
+                current.is_in_step = false;
+                if let TestResult::Fail(_, _, _) = result {
+                    return false;
+                }
+            }
+        }
+        _ => {}
+    }
+
+    true
+}
+
+/// Fails the scenario a child process died running, at the step it was on,
+/// and skips the steps after it.
+fn report_crash(
+    path: &Path,
+    rule: Option<&gherkin::Rule>,
+    current: Running,
+    status: ExitStatus,
+    stderr: Vec<u8>,
+    output: &mut dyn OutputVisitor,
+) {
+    let scenario = current.scenario;
+    let uri = relpath(path).display().to_string();
+    let mut stderr = Some(stderr);
+
+    for (n, step) in current
+        .steps
+        .iter()
+        .enumerate()
+        .skip(current.finished_steps)
+    {
+        if n > current.finished_steps || !current.is_in_step {
+            output.visit_step(rule, scenario, step);
+        }
+
+        let result = match stderr.take() {
+            Some(stderr) => TestResult::Fail(
+                PanicDetails {
+                    payload: format!("Isolated process crashed: {}", status),
+                    location: format!("{}:{}:{}", uri, step.position.0, step.position.1),
+                },
+                vec![],
+                stderr,
+            ),
+            None => TestResult::Skipped,
+        };
+        output.visit_step_result(rule, scenario, step, &result, Duration::ZERO);
+    }
+
+    // Every step had finished, so the crash was in an after hook, which is
+    // reported as a failed step of its own for the scenario to fail.
+    if let Some(stderr) = stderr {
+        let hooks = gherkin::Step {
+            ty: gherkin::StepType::Then,
+            raw_type: "After".to_owned(),
+            value: "hooks".to_owned(),
+            docstring: None,
+            table: None,
+            position: scenario.position,
+        };
+        let result = TestResult::Fail(
+            PanicDetails {
+                payload: format!("Isolated process crashed after the steps: {}", status),
+                location: format!("{}:{}:{}", uri, scenario.position.0, scenario.position.1),
+            },
+            vec![],
+            stderr,
+        );
+        output.visit_step(rule, scenario, &hooks);
+        output.visit_step_result(rule, scenario, &hooks, &result, Duration::ZERO);
+    }
+
+    output.visit_scenario_end(rule, scenario, current.started.elapsed());
+}
+
+/// Splits a line of a child's stdout into the output of the steps before an
+/// event, and the event.
+///
+/// A step that prints without a newline leaves its output at the start of the
+/// line with the next event. A line that only looks like an event, such as one
+/// a step printed, is not one, and is left as output.
+fn parse_event(line: &[u8]) -> Option<(&[u8], Value)> {
+    let prefix = EVENT_PREFIX.as_bytes();
+    let start = line
+        .windows(prefix.len())
+        .position(|window| window == prefix)?;
+    let event = serde_json::from_slice(&line[start + prefix.len()..]).ok()?;
+    Some((&line[..start], event))
+}
+
+/// Writes output of the steps of a child process to stdout.
+fn pass_through(output: &[u8]) {
+    if output.is_empty() {
+        return;
+    }
+    let mut stdout = io::stdout();
+    let _ = stdout.write_all(output);
+    let _ = stdout.flush();
+}
+
+/// Runs `scenarios` of the feature at `path` in child processes, reporting
+/// their events to `output` as they come in.
+///
+/// When a child process dies, the scenario it was running fails with the
+/// step it was on, and the scenarios after it are run in a new one.
+pub(crate) fn run_batch(
+    path: &Path,
+    feature: &gherkin::Feature,
+    rule: Option<&gherkin::Rule>,
+    scenarios: &[gherkin::Scenario],
+    output: &mut dyn OutputVisitor,
+) -> bool {
+    let mut is_success = true;
+    let mut remaining = scenarios.iter().collect::<Vec<_>>();
+
+    while !remaining.is_empty() {
+        let locations = remaining
+            .iter()
+            .map(|scenario| format!("{}:{}", path.display(), scenario.position.0))
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        let mut child = Command::new(env::current_exe().expect("invalid current executable"))
+            .args(env::args_os().skip(1))
+            .env(SCENARIOS_VAR, locations)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .expect("failed to start an isolated process");
+
+        let mut child_stderr = child.stderr.take().expect("child stderr is piped");
+        let stderr_reader = thread::spawn(move || {
+            let mut buffer = vec![];
+            let _ = child_stderr.read_to_end(&mut buffer);
+            buffer
+        });
+
+        let mut running: Option<Running> = None;
+        let mut done = vec![];
+        let mut is_finished = false;
+
+        let mut stdout = BufReader::new(child.stdout.take().expect("child stdout is piped"));
+        let mut line = vec![];
+        while stdout.read_until(b'\n', &mut line).unwrap_or(0) > 0 {
+            match parse_event(&line) {
+                Some((before, event)) => {
+                    pass_through(before);
+                    match event["event"].as_str() {
+                        Some("finish") => is_finished = true,
+                        Some("scenario_end") => {
+                            let duration = Duration::from_nanos(
+                                event["duration"].as_u64().unwrap_or_default(),
+                            );
+                            if let Some(current) = running.take() {
+                                output.visit_scenario_end(rule, current.scenario, duration);
+                                done.push(current.scenario.position.0);
+                            }
+                        }
+                        Some("scenario") => {
+                            let line = event["line"].as_u64().unwrap_or_default() as usize;
+                            if let Some(scenario) = remaining.iter().find(|s| s.position.0 == line)
+                            {
+                                output.visit_scenario(rule, scenario);
+                                running = Some(Running::new(feature, scenario));
+                            }
+                        }
+                        _ => {
+                            if let Some(current) = running.as_mut() {
+                                if !replay(&event, rule, current, output) {
+                                    is_success = false;
+                                }
+                            }
+                        }
+                    }
+                }
+                None => pass_through(&line),
+            }
+            line.clear();
+        }
+
+        let status = child
+            .wait()
+            .expect("failed to wait for an isolated process");
+        let stderr = stderr_reader.join().unwrap_or_default();
+
+        if is_finished {
+            let _ = io::stderr().write_all(&stderr);
+            break;
+        }
+
+        // The child died. Whatever it was running failed, or the first of the
+        // scenarios left if it died before starting any.
+        let current = match running.take() {
+            Some(current) => current,
+            None => {
+                let scenario = match remaining.iter().find(|s| !done.contains(&s.position.0)) {
+                    Some(scenario) => scenario,
+                    None => break,
+                };
+                output.visit_scenario(rule, scenario);
+                Running::new(feature, scenario)
+            }
+        };
+        done.push(current.scenario.position.0);
+        report_crash(path, rule, current, status, stderr, output);
+        is_success = false;
+
+        remaining.retain(|scenario| !done.contains(&scenario.position.0));
+    }
+
+    is_success
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::{parse_event, EVENT_PREFIX};
+
+    #[test]
+    fn finds_events_after_output_without_a_newline() {
+        let line = format!("partial{}{{\"event\":\"finish\"}}\n", EVENT_PREFIX);
+
+        let (before, event) = parse_event(line.as_bytes()).unwrap();
+        assert_eq!(before, b"partial");
+        assert_eq!(event, json!({ "event": "finish" }));
+    }
+
+    #[test]
+    fn leaves_lines_that_only_look_like_events() {
+        assert!(parse_event(b"just output\n").is_none());
+        assert!(parse_event(format!("{}not an event\n", EVENT_PREFIX).as_bytes()).is_none());
+        assert!(parse_event(format!("{}{{\"event\":", EVENT_PREFIX).as_bytes()).is_none());
+    }
+}
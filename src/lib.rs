@@ -8,25 +8,56 @@
 
 pub extern crate gherkin_rust as gherkin;
 pub extern crate globwalk;
+pub extern crate regex;
 
+#[doc(hidden)]
+pub mod capture;
 pub mod cli;
+mod docstring;
 mod hashable_regex;
-mod output;
+mod isolation;
+mod logs;
+pub mod merge;
+mod outline;
+pub mod output;
 mod panic_trap;
-
-use std::collections::HashMap;
+mod shard;
+mod shuffle;
+mod spans;
+mod table;
+#[cfg(test)]
+mod testing;
+
+use std::collections::{HashMap, HashSet};
 use std::fs::File;
 use std::io::{stderr, Read, Write};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
 use gherkin::Feature;
 pub use gherkin::{Scenario, Step, StepType};
-use regex::Regex;
+use regex::{Captures, Regex};
 
+#[doc(hidden)]
+pub use cucumber_rust_codegen::step_regex;
+pub use docstring::{DocstringError, DocstringExt};
 use hashable_regex::HashableRegex;
+pub use logs::LogRecord;
+#[cfg(feature = "capture-logs")]
+pub use logs::{CaptureLayer, CaptureLogger};
 pub use output::default::DefaultOutput;
-use output::OutputVisitor;
-use panic_trap::{PanicDetails, PanicTrap};
+pub use output::html::HtmlOutput;
+pub use output::json::JsonOutput;
+pub use output::junit::JUnitOutput;
+pub use output::messages::MessagesOutput;
+pub use output::multi::MultiOutput;
+pub use output::slowest::SlowestOutput;
+pub use output::{OutputVisitor, ReportOutput};
+pub use panic_trap::{spawn, PanicDetails};
+use panic_trap::{PanicTrap, ScenarioGuard};
+use shuffle::Rng;
+use spans::Span;
+pub use table::{TableError, TableExt};
 
 pub trait World: Default {}
 
@@ -34,9 +65,15 @@ type HelperFn = fn(&Scenario) -> ();
 
 type TestFn<W> = fn(&mut W, &Step) -> ();
 type TestRegexFn<W> = fn(&mut W, &[String], &Step) -> ();
+type TestCapturesFn<W> = fn(&mut W, &Captures<'_>, &Step) -> ();
 
 type TestBag<W> = HashMap<&'static str, TestFn<W>>;
-type RegexBag<W> = HashMap<HashableRegex, TestRegexFn<W>>;
+type RegexBag<W> = HashMap<HashableRegex, RegexFn<W>>;
+
+enum RegexFn<W> {
+    Matches(TestRegexFn<W>),
+    Captures(TestCapturesFn<W>),
+}
 
 #[derive(Default)]
 pub struct Steps<W: World> {
@@ -56,6 +93,7 @@ struct RegexSteps<W: World> {
 enum TestCaseType<'a, W: 'a + Default> {
     Normal(&'a TestFn<W>),
     Regex(&'a TestRegexFn<W>, Vec<String>),
+    Captures(&'a TestCapturesFn<W>, Captures<'a>),
 }
 
 pub enum TestResult {
@@ -66,6 +104,61 @@ pub enum TestResult {
     Fail(PanicDetails, Vec<u8>, Vec<u8>),
 }
 
+/// The kind of a hook run around each scenario.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HookType {
+    Before,
+    After,
+}
+
+/// A step registered with `Steps`, as reported to outputs.
+#[derive(Debug, Clone)]
+pub struct StepDefinition {
+    pub ty: StepType,
+    pub pattern: StepPattern,
+}
+
+#[derive(Debug, Clone)]
+pub enum StepPattern {
+    /// A step matching this exact text.
+    Text(&'static str),
+    Regex(Regex),
+}
+
+impl StepDefinition {
+    /// An identifier for the step definition that is the same from run to run.
+    pub fn id(&self) -> String {
+        match &self.pattern {
+            StepPattern::Text(text) => format!("{} {}", self.ty.as_str(), text),
+            StepPattern::Regex(regex) => format!("{} /{}/", self.ty.as_str(), regex.as_str()),
+        }
+    }
+
+    /// Whether `step` would be run by this step definition, were there no
+    /// other matching definitions.
+    pub fn matches(&self, step: &Step) -> bool {
+        if step.ty != self.ty {
+            return false;
+        }
+
+        match &self.pattern {
+            StepPattern::Text(text) => step.value == *text,
+            StepPattern::Regex(regex) => regex.is_match(&step.value),
+        }
+    }
+
+    /// The step definition that runs `step`, out of `definitions` as listed
+    /// by `Steps::definitions`, which is the first to match it.
+    pub(crate) fn find<'a>(
+        definitions: &'a [StepDefinition],
+        step: &Step,
+    ) -> Option<&'a StepDefinition> {
+        definitions
+            .iter()
+            .find(|definition| definition.matches(step))
+    }
+}
+
 impl<W: World> Steps<W> {
     fn test_bag_for(&self, ty: StepType) -> &TestBag<W> {
         match ty {
@@ -83,6 +176,7 @@ impl<W: World> Steps<W> {
         }
     }
 
+    #[allow(clippy::mutable_key_type)]
     fn regex_bag_for(&self, ty: StepType) -> &RegexBag<W> {
         match ty {
             StepType::Given => &self.regex.given,
@@ -91,6 +185,7 @@ impl<W: World> Steps<W> {
         }
     }
 
+    #[allow(clippy::mutable_key_type)]
     fn regex_bag_mut_for(&mut self, ty: StepType) -> &mut RegexBag<W> {
         match ty {
             StepType::Given => &mut self.regex.given,
@@ -99,29 +194,42 @@ impl<W: World> Steps<W> {
         }
     }
 
-    fn test_type<'a>(&'a self, step: &Step) -> Option<TestCaseType<'a, W>> {
+    fn test_type<'a>(&'a self, step: &'a Step) -> Option<TestCaseType<'a, W>> {
         if let Some(t) = self.test_bag_for(step.ty).get(&*step.value) {
             return Some(TestCaseType::Normal(t));
         }
 
+        // Of several matching regexes, the one `definitions` lists first runs,
+        // so that outputs name the step definition that ran.
         if let Some((regex, t)) = self
             .regex_bag_for(step.ty)
             .iter()
-            .find(|(regex, _)| regex.is_match(&step.value))
+            .filter(|(regex, _)| regex.is_match(&step.value))
+            .min_by_key(|(regex, _)| {
+                StepDefinition {
+                    ty: step.ty,
+                    pattern: StepPattern::Regex(regex.0.clone()),
+                }
+                .id()
+            })
         {
-            let matches = regex
-                .0
-                .captures(&step.value)
-                .unwrap()
-                .iter()
-                .map(|match_| {
-                    match_
-                        .map(|match_| match_.as_str().to_owned())
-                        .unwrap_or_default()
-                })
-                .collect();
-
-            return Some(TestCaseType::Regex(t, matches));
+            let captures = regex.0.captures(&step.value).unwrap();
+
+            return Some(match t {
+                RegexFn::Matches(t) => {
+                    let matches = captures
+                        .iter()
+                        .map(|match_| {
+                            match_
+                                .map(|match_| match_.as_str().to_owned())
+                                .unwrap_or_default()
+                        })
+                        .collect();
+
+                    TestCaseType::Regex(t, matches)
+                }
+                RegexFn::Captures(t) => TestCaseType::Captures(t, captures),
+            });
         }
 
         None
@@ -131,12 +239,29 @@ impl<W: World> Steps<W> {
         self.test_bag_mut_for(ty).insert(name, test_fn);
     }
 
+    /// Adds a regex step, compiling the regex at runtime.
+    ///
+    /// Panics if `regex` is not a valid regular expression. The `steps!` macro
+    /// uses `add_compiled_regex` instead, which has its regexes checked at
+    /// compile time.
     pub fn add_regex(&mut self, ty: StepType, regex: &str, test_fn: TestRegexFn<W>) {
         let regex = Regex::new(regex)
             .unwrap_or_else(|_| panic!("`{}` is not a valid regular expression", regex));
 
+        self.add_compiled_regex(ty, &regex, test_fn);
+    }
+
+    pub fn add_compiled_regex(&mut self, ty: StepType, regex: &Regex, test_fn: TestRegexFn<W>) {
+        self.regex_bag_mut_for(ty)
+            .insert(HashableRegex(regex.clone()), RegexFn::Matches(test_fn));
+    }
+
+    /// Adds a regex step that is given the regex captures as-is, so that it
+    /// can tell a capture group that did not participate in the match apart
+    /// from one that matched an empty string.
+    pub fn add_typed_regex(&mut self, ty: StepType, regex: &Regex, test_fn: TestCapturesFn<W>) {
         self.regex_bag_mut_for(ty)
-            .insert(HashableRegex(regex), test_fn);
+            .insert(HashableRegex(regex.clone()), RegexFn::Captures(test_fn));
     }
 
     pub fn combine(iter: impl Iterator<Item = Self>) -> Self {
@@ -155,6 +280,42 @@ impl<W: World> Steps<W> {
         combined
     }
 
+    /// All registered step definitions, in a stable order with the step
+    /// definitions matching exact text before those matching a regex, as they
+    /// take precedence when both match.
+    pub fn definitions(&self) -> Vec<StepDefinition> {
+        let types = [StepType::Given, StepType::When, StepType::Then];
+
+        let mut texts = types
+            .iter()
+            .flat_map(|&ty| {
+                self.test_bag_for(ty)
+                    .keys()
+                    .map(move |&text| StepDefinition {
+                        ty,
+                        pattern: StepPattern::Text(text),
+                    })
+            })
+            .collect::<Vec<_>>();
+        texts.sort_by_key(StepDefinition::id);
+
+        let mut regexes = types
+            .iter()
+            .flat_map(|&ty| {
+                self.regex_bag_for(ty)
+                    .keys()
+                    .map(move |regex| StepDefinition {
+                        ty,
+                        pattern: StepPattern::Regex(regex.0.clone()),
+                    })
+            })
+            .collect::<Vec<_>>();
+        regexes.sort_by_key(StepDefinition::id);
+
+        texts.extend(regexes);
+        texts
+    }
+
     fn run_test(
         &self,
         world: &mut W,
@@ -163,8 +324,9 @@ impl<W: World> Steps<W> {
         suppress_output: bool,
     ) -> TestResult {
         let test_result = PanicTrap::run(suppress_output, move || match test_type {
-            TestCaseType::Normal(t) => t(world, &step),
-            TestCaseType::Regex(t, ref c) => t(world, c, &step),
+            TestCaseType::Normal(t) => t(world, step),
+            TestCaseType::Regex(t, ref c) => t(world, c, step),
+            TestCaseType::Captures(t, ref c) => t(world, c, step),
         });
 
         match test_result.result {
@@ -182,20 +344,27 @@ impl<W: World> Steps<W> {
     #[allow(clippy::too_many_arguments)]
     fn run_scenario(
         &self,
+        path: &Path,
         feature: &gherkin::Feature,
         rule: Option<&gherkin::Rule>,
         scenario: &gherkin::Scenario,
         before_fns: &Option<&[HelperFn]>,
         after_fns: &Option<&[HelperFn]>,
         suppress_output: bool,
-        output: &mut impl OutputVisitor,
+        output: &mut dyn OutputVisitor,
     ) -> bool {
-        output.visit_scenario(rule, &scenario);
+        let scenario_started = Instant::now();
+        let _scenario = ScenarioGuard::enter();
+        let span = Span::scenario(path, scenario);
+        output.visit_scenario(rule, scenario);
+        logs::start();
 
         if let Some(before_fns) = before_fns {
+            let started = Instant::now();
             for f in before_fns.iter() {
-                f(&scenario);
+                f(scenario);
             }
+            output.visit_hook(rule, scenario, HookType::Before, started.elapsed());
         }
 
         let mut world = {
@@ -211,7 +380,7 @@ impl<W: World> Steps<W> {
                         eprintln!("Captured output was:");
                         Write::write(&mut stderr(), &panic_trap.stdout).unwrap();
                     }
-                    panic!(panic_info.payload);
+                    panic!("{}", panic_info.payload);
                 }
             }
         };
@@ -222,30 +391,51 @@ impl<W: World> Steps<W> {
         let steps = feature
             .background
             .iter()
-            .map(|bg| bg.steps.iter())
-            .flatten()
+            .flat_map(|bg| bg.steps.iter())
             .chain(scenario.steps.iter());
 
         for step in steps {
-            output.visit_step(rule, &scenario, &step);
+            let step_span = Span::step(path, step);
+            output.visit_step(rule, scenario, step);
 
-            let test_type = match self.test_type(&step) {
+            let test_type = match self.test_type(step) {
                 Some(v) => v,
                 None => {
-                    output.visit_step_result(rule, &scenario, &step, &TestResult::Unimplemented);
+                    output.visit_step_result(
+                        rule,
+                        scenario,
+                        step,
+                        &TestResult::Unimplemented,
+                        Duration::ZERO,
+                    );
+                    step_span.record_result(&TestResult::Unimplemented);
                     if !is_skipping {
                         is_skipping = true;
-                        output.visit_scenario_skipped(rule, &scenario);
+                        output.visit_scenario_skipped(rule, scenario);
                     }
                     continue;
                 }
             };
 
             if is_skipping {
-                output.visit_step_result(rule, &scenario, &step, &TestResult::Skipped);
+                output.visit_step_result(
+                    rule,
+                    scenario,
+                    step,
+                    &TestResult::Skipped,
+                    Duration::ZERO,
+                );
+                step_span.record_result(&TestResult::Skipped);
             } else {
-                let result = self.run_test(&mut world, test_type, &step, suppress_output);
-                output.visit_step_result(rule, &scenario, &step, &result);
+                let started = Instant::now();
+                let (result, records) =
+                    logs::capture(|| self.run_test(&mut world, test_type, step, suppress_output));
+                let duration = started.elapsed();
+                if !records.is_empty() {
+                    output.visit_logs(rule, scenario, Some(step), &records);
+                }
+                output.visit_step_result(rule, scenario, step, &result, duration);
+                step_span.record_result(&result);
                 match result {
                     TestResult::Pass => {}
                     TestResult::Fail(_, _, _) => {
@@ -254,19 +444,31 @@ impl<W: World> Steps<W> {
                     }
                     _ => {
                         is_skipping = true;
-                        output.visit_scenario_skipped(rule, &scenario);
+                        output.visit_scenario_skipped(rule, scenario);
                     }
                 };
             }
         }
 
         if let Some(after_fns) = after_fns {
+            let started = Instant::now();
             for f in after_fns.iter() {
-                f(&scenario);
+                f(scenario);
             }
+            output.visit_hook(rule, scenario, HookType::After, started.elapsed());
         }
 
-        output.visit_scenario_end(rule, &scenario);
+        let records = logs::take();
+        if !records.is_empty() {
+            output.visit_logs(rule, scenario, None, &records);
+        }
+
+        if is_success && is_skipping {
+            span.record_skipped();
+        } else {
+            span.record_success(is_success);
+        }
+        output.visit_scenario_end(rule, scenario, scenario_started.elapsed());
 
         is_success
     }
@@ -274,17 +476,33 @@ impl<W: World> Steps<W> {
     #[allow(clippy::too_many_arguments)]
     fn run_scenarios(
         &self,
+        path: &Path,
         feature: &gherkin::Feature,
         rule: Option<&gherkin::Rule>,
         scenarios: &[gherkin::Scenario],
         before_fns: Option<&[HelperFn]>,
         after_fns: Option<&[HelperFn]>,
         options: &cli::CliOptions,
-        output: &mut impl OutputVisitor,
+        rng: Option<&mut Rng>,
+        shard: Option<&HashSet<String>>,
+        output: &mut dyn OutputVisitor,
     ) -> bool {
         let mut is_success = true;
 
-        for scenario in scenarios {
+        let mut expanded = scenarios
+            .iter()
+            .flat_map(|scenario| {
+                outline::expand(scenario)
+                    .into_iter()
+                    .map(move |e| (scenario, e))
+            })
+            .collect::<Vec<_>>();
+        if let Some(rng) = rng {
+            rng.shuffle(&mut expanded);
+        }
+
+        let mut selected = vec![];
+        for (outline, scenario) in expanded {
             // If a tag is specified and the scenario does not have the tag, skip the test.
             let should_skip = match (&scenario.tags, &options.tag) {
                 (Some(ref tags), Some(ref tag)) => !tags.contains(tag),
@@ -295,6 +513,18 @@ impl<W: World> Steps<W> {
                 continue;
             }
 
+            // If scenarios were selected by location, skip the others.
+            if !options.is_selected(path, feature, rule, outline, &scenario) {
+                continue;
+            }
+
+            // If the scenarios are sharded, skip those of other shards.
+            if let Some(shard) = shard {
+                if !shard.contains(&shard::key(path, &scenario)) {
+                    continue;
+                }
+            }
+
             // If regex filter fails, skip the test.
             if let Some(ref regex) = options.filter {
                 if !regex.is_match(&scenario.name) {
@@ -302,16 +532,32 @@ impl<W: World> Steps<W> {
                 }
             }
 
-            if !self.run_scenario(
-                &feature,
-                rule,
-                &scenario,
-                &before_fns,
-                &after_fns,
-                options.suppress_output,
-                output,
-            ) {
-                is_success = false;
+            selected.push(scenario);
+        }
+
+        match options.isolate {
+            Some(batch) => {
+                for scenarios in selected.chunks(batch) {
+                    if !isolation::run_batch(path, feature, rule, scenarios, output) {
+                        is_success = false;
+                    }
+                }
+            }
+            None => {
+                for scenario in &selected {
+                    if !self.run_scenario(
+                        path,
+                        feature,
+                        rule,
+                        scenario,
+                        &before_fns,
+                        &after_fns,
+                        options.suppress_output,
+                        output,
+                    ) {
+                        is_success = false;
+                    }
+                }
             }
         }
 
@@ -320,14 +566,39 @@ impl<W: World> Steps<W> {
 
     pub fn run(
         &self,
-        feature_files: Vec<PathBuf>,
+        mut feature_files: Vec<PathBuf>,
         before_fns: Option<&[HelperFn]>,
         after_fns: Option<&[HelperFn]>,
         options: cli::CliOptions,
-        output: &mut impl OutputVisitor,
+        output: &mut dyn OutputVisitor,
     ) -> bool {
+        let run_started = Instant::now();
+        logs::install();
+        let _subscriber = logs::subscribe();
+        output.visit_step_definitions(&self.definitions());
         output.visit_start();
 
+        let mut rng = match options.order {
+            cli::Order::Defined => None,
+            cli::Order::Random(seed) => {
+                let message = format!("Randomized with seed {} (--order random:{})", seed, seed);
+                if options.stdout_is_report() {
+                    eprintln!("{}", message);
+                } else {
+                    println!("{}\n", message);
+                }
+                Some(Rng::new(seed))
+            }
+        };
+        if let Some(rng) = rng.as_mut() {
+            rng.shuffle(&mut feature_files);
+        }
+
+        let shard = options
+            .shard
+            .as_ref()
+            .map(|shard| shard::assign(shard, &feature_files));
+
         let mut is_success = true;
 
         for path in feature_files {
@@ -335,7 +606,9 @@ impl<W: World> Steps<W> {
             let mut buffer = String::new();
             file.read_to_string(&mut buffer).unwrap();
 
-            let feature = match Feature::try_from(&*buffer) {
+            let (buffer, openers) = docstring::strip_content_types(&buffer);
+
+            let feature = match Feature::try_from(&buffer) {
                 Ok(v) => v,
                 Err(e) => {
                     output.visit_feature_error(&path, &e);
@@ -343,39 +616,78 @@ impl<W: World> Steps<W> {
                     continue;
                 }
             };
+            docstring::keep_content_types(&feature, &openers);
+
+            // A shard leaves out the features and rules it has nothing of.
+            if let Some(shard) = &shard {
+                let scenarios = feature
+                    .scenarios
+                    .iter()
+                    .chain(feature.rules.iter().flat_map(|rule| rule.scenarios.iter()));
+                if !shard::any_assigned(shard, &path, scenarios) {
+                    continue;
+                }
+            }
 
+            let feature_started = Instant::now();
+            let span = Span::feature(&path, &feature);
+            let mut is_feature_success = true;
             output.visit_feature(&feature, &path);
             if !self.run_scenarios(
+                &path,
                 &feature,
                 None,
                 &feature.scenarios,
                 before_fns,
                 after_fns,
                 &options,
+                rng.as_mut(),
+                shard.as_ref(),
                 output,
             ) {
-                is_success = false;
+                is_feature_success = false;
+            }
+
+            let mut rules = feature.rules.iter().collect::<Vec<_>>();
+            if let Some(rng) = rng.as_mut() {
+                rng.shuffle(&mut rules);
             }
 
-            for rule in &feature.rules {
-                output.visit_rule(&rule);
-                if !self.run_scenarios(
+            for rule in rules {
+                if let Some(shard) = &shard {
+                    if !shard::any_assigned(shard, &path, &rule.scenarios) {
+                        continue;
+                    }
+                }
+
+                let rule_span = Span::rule(&path, rule);
+                output.visit_rule(rule);
+                let is_rule_success = self.run_scenarios(
+                    &path,
                     &feature,
-                    Some(&rule),
+                    Some(rule),
                     &rule.scenarios,
                     before_fns,
                     after_fns,
                     &options,
+                    rng.as_mut(),
+                    shard.as_ref(),
                     output,
-                ) {
-                    is_success = false;
+                );
+                rule_span.record_success(is_rule_success);
+                if !is_rule_success {
+                    is_feature_success = false;
                 }
-                output.visit_rule_end(&rule);
+                output.visit_rule_end(rule);
             }
-            output.visit_feature_end(&feature);
+            span.record_success(is_feature_success);
+            if !is_feature_success {
+                is_success = false;
+            }
+            output.visit_feature_end(&feature, feature_started.elapsed());
         }
 
-        output.visit_finish();
+        output.visit_finish(run_started.elapsed());
 
         is_success
     }
@@ -530,15 +842,18 @@ macro_rules! cucumber {
             use std::process;
             use $crate::globwalk::{glob, GlobWalkerBuilder};
             use $crate::gherkin::Scenario;
-            use $crate::{Steps, World, DefaultOutput};
+            use $crate::{Steps, World, MultiOutput};
             use $crate::cli::make_app;
 
             let options = match make_app() {
                 Ok(v) => v,
-                Err(e) => panic!(e)
+                Err(e) => {
+                    eprintln!("{}", e);
+                    process::exit(1);
+                }
             };
 
-            let walker = match &options.feature {
+            let walker = || match &options.feature {
                 Some(v) => glob(v).expect("feature glob is invalid"),
                 None => match Path::new($featurepath).canonicalize() {
                     Ok(p) => {
@@ -553,18 +868,25 @@ macro_rules! cucumber {
                         process::exit(1);
                     }
                 }
-            }.into_iter();
+            };
 
-            let mut feature_files = walker
-                .filter_map(Result::ok)
-                .map(|entry| entry.path().to_owned())
-                .collect::<Vec<_>>();
+            // Scenarios selected by location are looked for in their own files.
+            let mut feature_files = match &options.locations {
+                Some(locations) => locations
+                    .iter()
+                    .map(|location| location.path.clone())
+                    .collect::<Vec<_>>(),
+                None => walker()
+                    .into_iter()
+                    .filter_map(Result::ok)
+                    .map(|entry| entry.path().to_owned())
+                    .collect::<Vec<_>>(),
+            };
             feature_files.sort();
+            feature_files.dedup();
 
             let tests = Steps::combine($vec.iter().map(|f| f()));
 
-            let mut output = DefaultOutput::default();
-
             let setup_fn: Option<fn() -> ()> = $setupfn;
             let before_fns: Option<&[fn(&Scenario) -> ()]> = $beforefns;
             let after_fns: Option<&[fn(&Scenario) -> ()]> = $afterfns;
@@ -574,7 +896,13 @@ macro_rules! cucumber {
                 None => {}
             };
 
-            if !tests.run(feature_files, before_fns, after_fns, options, &mut output) {
+            let mut output = MultiOutput::from_options(&options).unwrap_or_else(|e| {
+                eprintln!("{}", e);
+                process::exit(1);
+            });
+            let is_success = tests.run(feature_files, before_fns, after_fns, options, &mut output);
+
+            if !is_success {
                 process::exit(1);
             }
         }
@@ -611,49 +939,98 @@ macro_rules! steps {
     (
         @parse_matches $worldtype:path, ($($arg_type:ty),*) $body:expr
     ) => {
-        |world: &mut $worldtype, matches, step| {
+        |world: &mut $worldtype, captures, step| {
+            #[allow(unused_imports)]
+            use $crate::capture::{ParseOptional as _, ParseRequired as _};
+
             let body: fn(&mut $worldtype, $($arg_type,)* &$crate::Step) -> () = $body;
-            let mut matches = matches.into_iter().enumerate().skip(1);
+            let mut captures = captures.iter().enumerate().skip(1);
 
             body(
                 world,
                 $({
-                    let (index, match_) = matches.next().unwrap();
-                    match_.parse::<$arg_type>().unwrap_or_else(|_| panic!("Failed to parse {}th argument '{}' to type {}", index, match_, stringify!($arg_type)))
+                    let (index, capture) = captures.next().unwrap();
+                    let capture = capture.map(|capture| capture.as_str());
+                    (&$crate::capture::Capture::<$arg_type>::new(index, capture, stringify!($arg_type))).parse_capture()
                 },)*
                 step
             )
         }
     };
 
+    (
+        @parse_named_captures $worldtype:path, $args_type:ty, $body:expr
+    ) => {
+        |world: &mut $worldtype, captures, step| {
+            let body: fn(&mut $worldtype, $args_type, &$crate::Step) -> () = $body;
+            let args = $crate::capture::deserialize_captures(captures, stringify!($args_type));
+
+            body(world, args, step)
+        }
+    };
+
     (
         @gather_steps, $worldtype:path, $tests:tt,
-        $ty:ident regex $name:tt $body:expr;
+        $ty:ident regex $name:tt ($($arg_type:ty),*) $body:expr;
     ) => {
-        $tests.add_regex(steps!(@step_type $ty), $name, $body);
+        $tests.add_typed_regex(
+            steps!(@step_type $ty),
+            $crate::step_regex!($crate, $name, ($($arg_type),*)),
+            steps!(@parse_matches $worldtype, ($($arg_type),*) $body)
+        );
     };
 
     (
         @gather_steps, $worldtype:path, $tests:tt,
-        $ty:ident regex $name:tt $body:expr; $( $items:tt )*
+        $ty:ident regex $name:tt ($($arg_type:ty),*) $body:expr; $( $items:tt )*
     ) => {
-        $tests.add_regex(steps!(@step_type $ty), $name, $body);
+        $tests.add_typed_regex(
+            steps!(@step_type $ty),
+            $crate::step_regex!($crate, $name, ($($arg_type),*)),
+            steps!(@parse_matches $worldtype, ($($arg_type),*) $body)
+        );
 
         steps!(@gather_steps, $worldtype, $tests, $( $items )*);
     };
 
     (
         @gather_steps, $worldtype:path, $tests:tt,
-        $ty:ident regex $name:tt ($($arg_type:ty),*) $body:expr;
+        $ty:ident regex $name:tt { $args_type:ty } $body:expr;
     ) => {
-        steps!(@gather_steps, $worldtype, $tests, $ty regex $name steps!(@parse_matches $worldtype, ($($arg_type),*) $body););
+        $tests.add_typed_regex(
+            steps!(@step_type $ty),
+            $crate::step_regex!($crate, $name, { $args_type }),
+            steps!(@parse_named_captures $worldtype, $args_type, $body)
+        );
     };
 
     (
         @gather_steps, $worldtype:path, $tests:tt,
-        $ty:ident regex $name:tt ($($arg_type:ty),*) $body:expr; $( $items:tt )*
+        $ty:ident regex $name:tt { $args_type:ty } $body:expr; $( $items:tt )*
+    ) => {
+        $tests.add_typed_regex(
+            steps!(@step_type $ty),
+            $crate::step_regex!($crate, $name, { $args_type }),
+            steps!(@parse_named_captures $worldtype, $args_type, $body)
+        );
+
+        steps!(@gather_steps, $worldtype, $tests, $( $items )*);
+    };
+
+    (
+        @gather_steps, $worldtype:path, $tests:tt,
+        $ty:ident regex $name:tt $body:expr;
     ) => {
-        steps!(@gather_steps, $worldtype, $tests, $ty regex $name steps!(@parse_matches $worldtype, ($($arg_type),*) $body); $( $items )*);
+        $tests.add_compiled_regex(steps!(@step_type $ty), $crate::step_regex!($crate, $name), $body);
+    };
+
+    (
+        @gather_steps, $worldtype:path, $tests:tt,
+        $ty:ident regex $name:tt $body:expr; $( $items:tt )*
+    ) => {
+        $tests.add_compiled_regex(steps!(@step_type $ty), $crate::step_regex!($crate, $name), $body);
+
+        steps!(@gather_steps, $worldtype, $tests, $( $items )*);
     };
 
     (
@@ -0,0 +1,385 @@
+use std::cell::RefCell;
+use std::fmt;
+
+/// A record logged through the `log` or `tracing` crates while a step or
+/// scenario was run, captured with the `capture-logs` feature.
+///
+/// Only records logged on the thread running the scenario are captured, and
+/// not those of threads its steps spawn.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogRecord {
+    /// The level, such as `INFO`.
+    pub level: String,
+    pub target: String,
+    pub message: String,
+}
+
+impl fmt::Display for LogRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:<5} {}: {}", self.level, self.target, self.message)
+    }
+}
+
+thread_local! {
+    // The records of the step or scenario being run on this thread, if their
+    // records are being captured.
+    static RECORDS: RefCell<Option<Vec<LogRecord>>> = const { RefCell::new(None) };
+}
+
+/// Starts capturing the records logged on this thread.
+pub(crate) fn start() {
+    let previous = RECORDS.with(|records| records.borrow_mut().replace(vec![]));
+    if previous.is_none() {
+        raise_level();
+    }
+}
+
+/// Stops capturing, returning the records logged since `start`.
+pub(crate) fn take() -> Vec<LogRecord> {
+    let records = RECORDS.with(|records| records.borrow_mut().take());
+    if records.is_some() {
+        restore_level();
+    }
+    records.unwrap_or_default()
+}
+
+/// Runs `f`, returning the records it logged apart from those captured
+/// around it.
+pub(crate) fn capture<T>(f: impl FnOnce() -> T) -> (T, Vec<LogRecord>) {
+    let outer = RECORDS.with(|records| records.borrow_mut().replace(vec![]));
+    let value = f();
+    let captured = RECORDS.with(|records| std::mem::replace(&mut *records.borrow_mut(), outer));
+    (value, captured.unwrap_or_default())
+}
+
+// Whether the `log` logger is one of this crate's, and so may change its level.
+#[cfg(feature = "capture-logs")]
+static OWNS_LOGGER: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+
+// How many threads are capturing, and the `log` level from before the first.
+#[cfg(feature = "capture-logs")]
+static CAPTURING: std::sync::Mutex<(usize, log::LevelFilter)> =
+    std::sync::Mutex::new((0, log::LevelFilter::Off));
+
+/// Lets every `log` record through while records are captured, on top of
+/// whatever level the program set for itself.
+#[cfg(feature = "capture-logs")]
+fn raise_level() {
+    use std::sync::atomic::Ordering;
+
+    if !OWNS_LOGGER.load(Ordering::SeqCst) {
+        return;
+    }
+    let mut capturing = CAPTURING.lock().unwrap_or_else(|e| e.into_inner());
+    if capturing.0 == 0 {
+        capturing.1 = log::max_level();
+        log::set_max_level(log::LevelFilter::Trace);
+    }
+    capturing.0 += 1;
+}
+
+/// Puts back the `log` level from before `raise_level` once nothing is
+/// captured.
+#[cfg(feature = "capture-logs")]
+fn restore_level() {
+    use std::sync::atomic::Ordering;
+
+    if !OWNS_LOGGER.load(Ordering::SeqCst) {
+        return;
+    }
+    let mut capturing = CAPTURING.lock().unwrap_or_else(|e| e.into_inner());
+    capturing.0 -= 1;
+    if capturing.0 == 0 {
+        log::set_max_level(capturing.1);
+    }
+}
+
+#[cfg(not(feature = "capture-logs"))]
+fn raise_level() {}
+
+#[cfg(not(feature = "capture-logs"))]
+fn restore_level() {}
+
+#[cfg(feature = "capture-logs")]
+fn push(record: LogRecord) {
+    RECORDS.with(|records| {
+        if let Some(records) = records.borrow_mut().as_mut() {
+            records.push(record);
+        }
+    });
+}
+
+/// Installs a `log` logger capturing the records of steps and scenarios,
+/// unless the program has set its own, which `CaptureLogger` can wrap instead.
+#[cfg(feature = "capture-logs")]
+pub(crate) fn install() {
+    use std::sync::Once;
+
+    static INSTALL: Once = Once::new();
+
+    INSTALL.call_once(|| {
+        if log::set_logger(&Logger).is_ok() {
+            OWNS_LOGGER.store(true, std::sync::atomic::Ordering::SeqCst);
+        }
+    });
+}
+
+#[cfg(not(feature = "capture-logs"))]
+pub(crate) fn install() {}
+
+/// Sets a `tracing` subscriber capturing the records of steps and scenarios
+/// as the default of this thread until the guard is dropped, unless the
+/// program has set one, which `CaptureLayer` can be added to instead.
+///
+/// It is only the default of this thread, so that the global one is left for
+/// the program to set.
+#[cfg(feature = "capture-logs")]
+pub(crate) fn subscribe() -> Option<tracing::subscriber::DefaultGuard> {
+    use tracing::subscriber::NoSubscriber;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    if tracing::dispatcher::get_default(|dispatch| !dispatch.is::<NoSubscriber>()) {
+        return None;
+    }
+    let subscriber = tracing_subscriber::registry().with(CaptureLayer);
+    Some(tracing::subscriber::set_default(subscriber))
+}
+
+#[cfg(not(feature = "capture-logs"))]
+pub(crate) fn subscribe() -> Option<()> {
+    None
+}
+
+#[cfg(feature = "capture-logs")]
+struct Logger;
+
+#[cfg(feature = "capture-logs")]
+impl log::Log for Logger {
+    fn enabled(&self, _metadata: &log::Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &log::Record) {
+        push(LogRecord::from(record));
+    }
+
+    fn flush(&self) {}
+}
+
+#[cfg(feature = "capture-logs")]
+impl From<&log::Record<'_>> for LogRecord {
+    fn from(record: &log::Record) -> LogRecord {
+        LogRecord {
+            level: record.level().to_string(),
+            target: record.target().to_string(),
+            message: record.args().to_string(),
+        }
+    }
+}
+
+/// A `log` logger capturing the records of steps and scenarios, for programs
+/// that set a logger of their own, which it wraps.
+///
+/// Every record goes on to the wrapped logger if it is enabled for it. Every
+/// level is let through while a scenario runs, so the wrapped logger should
+/// filter by level itself rather than leave it to `log::set_max_level`.
+#[cfg(feature = "capture-logs")]
+pub struct CaptureLogger<L> {
+    inner: L,
+}
+
+#[cfg(feature = "capture-logs")]
+impl<L: log::Log + 'static> CaptureLogger<L> {
+    pub fn new(inner: L) -> CaptureLogger<L> {
+        CaptureLogger { inner }
+    }
+
+    /// Sets this as the `log` logger, which can only be done once.
+    pub fn install(self) -> Result<(), log::SetLoggerError> {
+        log::set_logger(Box::leak(Box::new(self)))?;
+        OWNS_LOGGER.store(true, std::sync::atomic::Ordering::SeqCst);
+        Ok(())
+    }
+}
+
+#[cfg(feature = "capture-logs")]
+impl<L: log::Log> log::Log for CaptureLogger<L> {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        RECORDS.with(|records| records.borrow().is_some()) || self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &log::Record) {
+        push(LogRecord::from(record));
+        if self.inner.enabled(record.metadata()) {
+            self.inner.log(record);
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+/// A `tracing` layer capturing the events of steps and scenarios, for
+/// programs that set a subscriber of their own.
+#[cfg(feature = "capture-logs")]
+pub struct CaptureLayer;
+
+#[cfg(feature = "capture-logs")]
+impl<S: tracing::Subscriber> tracing_subscriber::Layer<S> for CaptureLayer {
+    fn on_event(
+        &self,
+        event: &tracing::Event<'_>,
+        _ctx: tracing_subscriber::layer::Context<'_, S>,
+    ) {
+        let mut message = MessageVisitor::default();
+        event.record(&mut message);
+
+        push(LogRecord {
+            level: event.metadata().level().to_string(),
+            target: event.metadata().target().to_string(),
+            message: message.0,
+        });
+    }
+}
+
+// Formats the fields of an event as its message followed by `name=value`.
+#[cfg(feature = "capture-logs")]
+#[derive(Default)]
+struct MessageVisitor(String);
+
+#[cfg(feature = "capture-logs")]
+impl tracing::field::Visit for MessageVisitor {
+    fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn fmt::Debug) {
+        use std::fmt::Write;
+
+        if !self.0.is_empty() {
+            self.0.push(' ');
+        }
+        if field.name() == "message" {
+            let _ = write!(self.0, "{:?}", value);
+        } else {
+            let _ = write!(self.0, "{}={:?}", field.name(), value);
+        }
+    }
+}
+
+#[cfg(all(test, feature = "capture-logs"))]
+mod tests {
+    use std::sync::Mutex;
+    use std::thread;
+
+    use log::Log;
+
+    use super::{capture, install, start, subscribe, take, CaptureLogger, LogRecord};
+    use crate::testing;
+
+    fn record(level: &str, message: &str) -> LogRecord {
+        LogRecord {
+            level: level.to_string(),
+            target: "logs".to_string(),
+            message: message.to_string(),
+        }
+    }
+
+    #[test]
+    fn captures_the_records_of_this_thread() {
+        let _lock = testing::lock();
+        install();
+        let _subscriber = subscribe();
+
+        log::info!(target: "logs", "before");
+        start();
+        log::info!(target: "logs", "logged");
+        tracing::warn!(target: "logs", answer = 42, "traced");
+        thread::spawn(|| log::info!(target: "logs", "on another thread"))
+            .join()
+            .unwrap();
+        let records = take();
+
+        assert_eq!(
+            records,
+            vec![record("INFO", "logged"), record("WARN", "traced answer=42")]
+        );
+        assert!(take().is_empty());
+    }
+
+    #[test]
+    fn keeps_the_records_of_a_step_apart() {
+        let _lock = testing::lock();
+        install();
+
+        start();
+        log::info!(target: "logs", "scenario");
+        let ((), step) = capture(|| log::debug!(target: "logs", "step"));
+        log::trace!(target: "logs", "scenario again");
+
+        assert_eq!(step, vec![record("DEBUG", "step")]);
+        assert_eq!(
+            take(),
+            vec![
+                record("INFO", "scenario"),
+                record("TRACE", "scenario again")
+            ]
+        );
+    }
+
+    #[test]
+    fn only_raises_the_level_while_capturing() {
+        let _lock = testing::lock();
+        install();
+
+        log::set_max_level(log::LevelFilter::Warn);
+        start();
+        assert_eq!(log::max_level(), log::LevelFilter::Trace);
+        let (_, _) = capture(|| assert_eq!(log::max_level(), log::LevelFilter::Trace));
+        assert_eq!(log::max_level(), log::LevelFilter::Trace);
+        take();
+        assert_eq!(log::max_level(), log::LevelFilter::Warn);
+
+        log::set_max_level(log::LevelFilter::Off);
+    }
+
+    // A program's own logger, keeping the messages of records at `Info` and
+    // above.
+    #[derive(Default)]
+    struct InfoLogger(Mutex<Vec<String>>);
+
+    impl Log for InfoLogger {
+        fn enabled(&self, metadata: &log::Metadata) -> bool {
+            metadata.level() <= log::Level::Info
+        }
+
+        fn log(&self, record: &log::Record) {
+            self.0.lock().unwrap().push(record.args().to_string());
+        }
+
+        fn flush(&self) {}
+    }
+
+    #[test]
+    fn captures_records_for_the_logger_it_wraps() {
+        let _lock = testing::lock();
+        let logger = CaptureLogger::new(InfoLogger::default());
+        let log = |level, message: &str| {
+            logger.log(
+                &log::Record::builder()
+                    .level(level)
+                    .target("logs")
+                    .args(format_args!("{}", message))
+                    .build(),
+            )
+        };
+
+        log(log::Level::Info, "before");
+        start();
+        log(log::Level::Info, "logged");
+        log(log::Level::Debug, "debugging");
+
+        assert_eq!(
+            take(),
+            vec![record("INFO", "logged"), record("DEBUG", "debugging")]
+        );
+        assert_eq!(*logger.inner.0.lock().unwrap(), vec!["before", "logged"]);
+    }
+}
@@ -0,0 +1,433 @@
+//! Merging the reports of several runs, such as the shards of `--shard`, into
+//! one report.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use serde_json::Value;
+
+use crate::output::escape;
+
+#[derive(Debug)]
+pub enum MergeError {
+    /// There were no reports to merge.
+    NoReports,
+    /// A report was neither Cucumber JSON nor JUnit XML.
+    UnknownFormat,
+    /// The reports were not all of the same format.
+    MixedFormats,
+    InvalidJson(serde_json::Error),
+    InvalidXml(quick_xml::Error),
+    /// A JUnit report was missing the named element or attribute.
+    InvalidJUnit(&'static str),
+}
+
+impl fmt::Display for MergeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MergeError::NoReports => write!(f, "no reports to merge"),
+            MergeError::UnknownFormat => {
+                write!(f, "a report is neither Cucumber JSON nor JUnit XML")
+            }
+            MergeError::MixedFormats => write!(f, "the reports are not all of the same format"),
+            MergeError::InvalidJson(e) => write!(f, "invalid Cucumber JSON report: {}", e),
+            MergeError::InvalidXml(e) => write!(f, "invalid JUnit report: {}", e),
+            MergeError::InvalidJUnit(missing) => {
+                write!(f, "invalid JUnit report: missing {}", missing)
+            }
+        }
+    }
+}
+
+impl std::error::Error for MergeError {}
+
+/// The format of a report.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportFormat {
+    Json,
+    JUnit,
+}
+
+impl ReportFormat {
+    /// Tells the format of `report` from its first character.
+    pub fn detect(report: &str) -> Result<ReportFormat, MergeError> {
+        match report.trim_start().chars().next() {
+            Some('[') => Ok(ReportFormat::Json),
+            Some('<') => Ok(ReportFormat::JUnit),
+            _ => Err(MergeError::UnknownFormat),
+        }
+    }
+}
+
+/// Merges reports written by the `json` or `junit` outputs into one report
+/// of the same format.
+///
+/// A scenario found in several reports, such as one that was retried, is
+/// taken from the last report it is in. Features and scenarios are ordered by
+/// path and line, and the JUnit totals are counted again from the merged
+/// scenarios.
+pub fn merge_reports(reports: &[String]) -> Result<String, MergeError> {
+    let format = match reports.first() {
+        Some(report) => ReportFormat::detect(report)?,
+        None => return Err(MergeError::NoReports),
+    };
+    for report in reports {
+        if ReportFormat::detect(report)? != format {
+            return Err(MergeError::MixedFormats);
+        }
+    }
+
+    match format {
+        ReportFormat::Json => merge_json(reports),
+        ReportFormat::JUnit => merge_junit(reports),
+    }
+}
+
+fn merge_json(reports: &[String]) -> Result<String, MergeError> {
+    // Features by uri, with their scenarios by line. A scenario comes with the
+    // background element written before it, if any.
+    let mut features: BTreeMap<String, (Value, BTreeMap<u64, Vec<Value>>)> = BTreeMap::new();
+
+    for report in reports {
+        let report: Vec<Value> = serde_json::from_str(report).map_err(MergeError::InvalidJson)?;
+
+        for mut feature in report {
+            let uri = feature["uri"].as_str().unwrap_or_default().to_string();
+            let elements = match feature["elements"].take() {
+                Value::Array(elements) => elements,
+                _ => vec![],
+            };
+            let (_, scenarios) = features
+                .entry(uri)
+                .or_insert_with(|| (feature, BTreeMap::new()));
+
+            let mut group = vec![];
+            for element in elements {
+                let is_background = element["type"] == "background";
+                let line = element["line"].as_u64().unwrap_or_default();
+                group.push(element);
+                if !is_background {
+                    scenarios.insert(line, std::mem::take(&mut group));
+                }
+            }
+        }
+    }
+
+    let merged = features
+        .into_values()
+        .map(|(mut feature, scenarios)| {
+            feature["elements"] = Value::Array(scenarios.into_values().flatten().collect());
+            feature
+        })
+        .collect::<Vec<_>>();
+
+    let merged = serde_json::to_string_pretty(&merged).expect("JSON values always serialize");
+    Ok(format!("{}\n", merged))
+}
+
+struct TestCase {
+    time: f64,
+    is_failure: bool,
+    is_error: bool,
+    is_skipped: bool,
+    // The element as it was written, which is kept as it is.
+    xml: String,
+}
+
+/// The unescaped value of the attribute `name` of `tag`.
+fn attribute(tag: &BytesStart<'_>, name: &str) -> Result<Option<String>, MergeError> {
+    for attribute in tag.attributes() {
+        let attribute = attribute.map_err(|e| MergeError::InvalidXml(e.into()))?;
+        if attribute.key.as_ref() == name.as_bytes() {
+            let value = attribute.unescape_value().map_err(MergeError::InvalidXml)?;
+            return Ok(Some(value.into_owned()));
+        }
+    }
+    Ok(None)
+}
+
+/// Reads the testcase that `tag`, found at `start` in `report`, opens, up to
+/// and including its end.
+fn read_testcase(
+    reader: &mut Reader<&[u8]>,
+    report: &str,
+    start: usize,
+    tag: &BytesStart<'_>,
+    is_empty: bool,
+) -> Result<TestCase, MergeError> {
+    let mut case = TestCase {
+        time: attribute(tag, "time")?
+            .and_then(|time| time.parse().ok())
+            .unwrap_or_default(),
+        is_failure: false,
+        is_error: false,
+        is_skipped: false,
+        xml: String::new(),
+    };
+
+    // Only the testcase's own children tell its result, not anything nested
+    // in its output.
+    let mut is_open = !is_empty;
+    let mut depth = 0;
+    while is_open {
+        let (child, is_start) = match reader.read_event().map_err(MergeError::InvalidXml)? {
+            Event::Start(child) => (child, true),
+            Event::Empty(child) => (child, false),
+            Event::End(_) if depth == 0 => {
+                is_open = false;
+                continue;
+            }
+            Event::End(_) => {
+                depth -= 1;
+                continue;
+            }
+            Event::Eof => return Err(MergeError::InvalidJUnit("testcase end")),
+            _ => continue,
+        };
+        if depth == 0 {
+            match child.name().as_ref() {
+                b"failure" => case.is_failure = true,
+                b"error" => case.is_error = true,
+                b"skipped" => case.is_skipped = true,
+                _ => {}
+            }
+        }
+        if is_start {
+            depth += 1;
+        }
+    }
+
+    case.xml = report[start..reader.buffer_position() as usize].to_string();
+    Ok(case)
+}
+
+fn merge_junit(reports: &[String]) -> Result<String, MergeError> {
+    // Suites by file and name, with their cases by line and name.
+    let mut suites: BTreeMap<(String, String), BTreeMap<(usize, String), TestCase>> =
+        BTreeMap::new();
+
+    for report in reports {
+        let mut reader = Reader::from_str(report);
+        let mut suite = None;
+
+        loop {
+            let start = reader.buffer_position() as usize;
+            let (tag, is_empty) = match reader.read_event().map_err(MergeError::InvalidXml)? {
+                Event::Start(tag) => (tag, false),
+                Event::Empty(tag) => (tag, true),
+                Event::End(tag) if tag.name().as_ref() == b"testsuite" => {
+                    suite = None;
+                    continue;
+                }
+                Event::Eof => break,
+                _ => continue,
+            };
+
+            match tag.name().as_ref() {
+                b"testsuite" => {
+                    let name = attribute(&tag, "name")?
+                        .ok_or(MergeError::InvalidJUnit("testsuite name"))?;
+                    let file = attribute(&tag, "file")?.unwrap_or_default();
+                    suites.entry((file.clone(), name.clone())).or_default();
+                    if !is_empty {
+                        suite = Some((file, name));
+                    }
+                }
+                b"testcase" => {
+                    let key = suite
+                        .clone()
+                        .ok_or(MergeError::InvalidJUnit("testsuite of a testcase"))?;
+                    let name = attribute(&tag, "name")?
+                        .ok_or(MergeError::InvalidJUnit("testcase name"))?;
+                    let line = attribute(&tag, "line")?
+                        .and_then(|line| line.parse().ok())
+                        .unwrap_or_default();
+                    let case = read_testcase(&mut reader, report, start, &tag, is_empty)?;
+                    suites.entry(key).or_default().insert((line, name), case);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    let all = || suites.values().flat_map(|cases| cases.values());
+    let mut out = String::new();
+    out.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    out.push_str(&format!(
+        "<testsuites name=\"cucumber\" tests=\"{}\" failures=\"{}\" errors=\"{}\" skipped=\"{}\" time=\"{:.3}\">\n",
+        all().count(),
+        all().filter(|c| c.is_failure).count(),
+        all().filter(|c| c.is_error).count(),
+        all().filter(|c| c.is_skipped).count(),
+        all().map(|c| c.time).sum::<f64>(),
+    ));
+
+    for ((file, name), cases) in &suites {
+        out.push_str(&format!(
+            "  <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" errors=\"{}\" skipped=\"{}\" time=\"{:.3}\" file=\"{}\">\n",
+            escape(name),
+            cases.len(),
+            cases.values().filter(|c| c.is_failure).count(),
+            cases.values().filter(|c| c.is_error).count(),
+            cases.values().filter(|c| c.is_skipped).count(),
+            cases.values().map(|c| c.time).sum::<f64>(),
+            escape(file),
+        ));
+        for case in cases.values() {
+            out.push_str(&format!("    {}\n", case.xml));
+        }
+        out.push_str("  </testsuite>\n");
+    }
+
+    out.push_str("</testsuites>\n");
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use super::{merge_reports, MergeError};
+
+    fn merge(reports: &[&str]) -> Result<String, MergeError> {
+        merge_reports(&reports.iter().map(|r| r.to_string()).collect::<Vec<_>>())
+    }
+
+    fn element(ty: &str, line: u64, status: &str) -> Value {
+        json!({
+            "type": ty,
+            "line": line,
+            "steps": [{ "result": { "status": status } }]
+        })
+    }
+
+    #[test]
+    fn merges_json_features_by_uri_and_scenarios_by_line() {
+        let first = json!([
+            {
+                "uri": "b.feature",
+                "elements": [
+                    element("background", 3, "passed"),
+                    element("scenario", 6, "failed"),
+                    element("background", 3, "passed"),
+                    element("scenario", 9, "passed")
+                ]
+            },
+            { "uri": "a.feature", "elements": [element("scenario", 3, "passed")] }
+        ]);
+        // The scenario at line 6 again, as retried.
+        let second = json!([
+            {
+                "uri": "b.feature",
+                "elements": [
+                    element("background", 3, "passed"),
+                    element("scenario", 6, "passed")
+                ]
+            }
+        ]);
+
+        let merged: Value =
+            serde_json::from_str(&merge(&[&first.to_string(), &second.to_string()]).unwrap())
+                .unwrap();
+
+        assert_eq!(
+            merged,
+            json!([
+                { "uri": "a.feature", "elements": [element("scenario", 3, "passed")] },
+                {
+                    "uri": "b.feature",
+                    "elements": [
+                        element("background", 3, "passed"),
+                        element("scenario", 6, "passed"),
+                        element("background", 3, "passed"),
+                        element("scenario", 9, "passed")
+                    ]
+                }
+            ])
+        );
+    }
+
+    const SHARD_1: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<testsuites name="cucumber" tests="3" failures="1" errors="0" skipped="1" time="3.000">
+  <testsuite name="Feature &gt; one" tests="3" failures="1" errors="0" skipped="1" time="3.000" file="a.feature">
+    <testcase name="retried" classname="one" time="1.000" file="a.feature" line="3"><failure message="a > b" type="panic"><![CDATA[</testcase> is not the end]]></failure></testcase>
+    <testcase name="skipped" classname="one" time="1.000" file="a.feature" line="6"><skipped message="undefined"/></testcase>
+    <testcase name="passed" classname="one" time="1.000" file="a.feature" line="9"><!-- <failure/> --><system-out><![CDATA[<error/>]]></system-out></testcase>
+  </testsuite>
+</testsuites>
+"#;
+
+    const SHARD_2: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<testsuites name="cucumber" tests="2" failures="0" errors="1" skipped="0" time="2.500">
+  <testsuite name="Feature &gt; one" tests="1" failures="0" errors="0" skipped="0" time="0.500" file="a.feature">
+    <testcase name="retried" classname="one" time="0.500" file="a.feature" line="3"/>
+  </testsuite>
+  <testsuite name="Two" tests="1" failures="0" errors="1" skipped="0" time="2.000" file="b.feature">
+    <testcase name="errored" classname="Two" time="2.000" file="b.feature" line="4"><error message="crashed"/></testcase>
+  </testsuite>
+</testsuites>
+"#;
+
+    #[test]
+    fn merges_junit_scenarios_taking_the_last_of_each() {
+        let merged = merge(&[SHARD_1, SHARD_2]).unwrap();
+
+        assert_eq!(
+            merged,
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<testsuites name="cucumber" tests="4" failures="0" errors="1" skipped="1" time="4.500">
+  <testsuite name="Feature &gt; one" tests="3" failures="0" errors="0" skipped="1" time="2.500" file="a.feature">
+    <testcase name="retried" classname="one" time="0.500" file="a.feature" line="3"/>
+    <testcase name="skipped" classname="one" time="1.000" file="a.feature" line="6"><skipped message="undefined"/></testcase>
+    <testcase name="passed" classname="one" time="1.000" file="a.feature" line="9"><!-- <failure/> --><system-out><![CDATA[<error/>]]></system-out></testcase>
+  </testsuite>
+  <testsuite name="Two" tests="1" failures="0" errors="1" skipped="0" time="2.000" file="b.feature">
+    <testcase name="errored" classname="Two" time="2.000" file="b.feature" line="4"><error message="crashed"/></testcase>
+  </testsuite>
+</testsuites>
+"#
+        );
+    }
+
+    #[test]
+    fn counts_junit_totals_from_the_merged_scenarios() {
+        // The failure at line 3 is not replaced when its shard comes last.
+        let merged = merge(&[SHARD_2, SHARD_1]).unwrap();
+
+        assert!(merged.contains(
+            r#"<testsuites name="cucumber" tests="4" failures="1" errors="1" skipped="1" time="5.000">"#
+        ));
+        assert!(merged.contains(
+            r#"<testsuite name="Feature &gt; one" tests="3" failures="1" errors="0" skipped="1" time="3.000" file="a.feature">"#
+        ));
+        assert!(merged.contains("<![CDATA[</testcase> is not the end]]>"));
+    }
+
+    #[test]
+    fn rejects_reports_that_cannot_be_merged() {
+        assert!(matches!(merge(&[]), Err(MergeError::NoReports)));
+        assert!(matches!(
+            merge(&["nothing"]),
+            Err(MergeError::UnknownFormat)
+        ));
+        assert!(matches!(
+            merge(&["[]", SHARD_1]),
+            Err(MergeError::MixedFormats)
+        ));
+        assert!(matches!(merge(&["[{]"]), Err(MergeError::InvalidJson(_))));
+        assert!(matches!(
+            merge(&["<testsuites><testsuite></testsuite></testsuites>"]),
+            Err(MergeError::InvalidJUnit("testsuite name"))
+        ));
+        assert!(matches!(
+            merge(&[r#"<testsuite name="a"><testcase name="b"></testsuite>"#]),
+            Err(MergeError::InvalidXml(_))
+        ));
+        assert!(matches!(
+            merge(&[r#"<testsuite name="a"><testcase name="b">"#]),
+            Err(MergeError::InvalidJUnit("testcase end"))
+        ));
+    }
+}
@@ -0,0 +1,136 @@
+use gherkin::{Scenario, Step, Table};
+
+use crate::{docstring, table};
+
+/// Expands a scenario outline into one scenario per row of its examples, with
+/// the `<placeholders>` in its name and steps replaced by the row's values.
+///
+/// Each expanded scenario is positioned at its row of the examples table, and
+/// its name is suffixed with the number of the row. Scenarios without examples
+/// are returned as they are.
+pub(crate) fn expand(scenario: &Scenario) -> Vec<Scenario> {
+    let examples = match &scenario.examples {
+        Some(examples) => examples,
+        None => return vec![scenario.clone()],
+    };
+
+    let tags = match (&scenario.tags, &examples.tags) {
+        (Some(a), Some(b)) => Some(a.iter().chain(b.iter()).cloned().collect()),
+        (a, b) => a.clone().or_else(|| b.clone()),
+    };
+
+    examples
+        .table
+        .rows
+        .iter()
+        .enumerate()
+        .map(|(n, row)| {
+            let fill = |s: &str| {
+                examples
+                    .table
+                    .header
+                    .iter()
+                    .zip(row.iter())
+                    .fold(s.to_string(), |s, (key, value)| {
+                        s.replace(&format!("<{}>", key), value)
+                    })
+            };
+
+            Scenario {
+                name: format!("{} (example {})", fill(&scenario.name), n + 1),
+                steps: scenario
+                    .steps
+                    .iter()
+                    .map(|step| {
+                        let expanded = Step {
+                            value: fill(&step.value),
+                            docstring: step.docstring.as_ref().map(|s| fill(s)),
+                            table: step.table.as_ref().map(|t| Table {
+                                header: t.header.iter().map(|s| fill(s)).collect(),
+                                rows: t
+                                    .rows
+                                    .iter()
+                                    .map(|r| r.iter().map(|s| fill(s)).collect())
+                                    .collect(),
+                                position: t.position,
+                            }),
+                            ..step.clone()
+                        };
+                        docstring::keep_content_type_of_expanded(step, &expanded);
+                        expanded
+                    })
+                    .collect(),
+                examples: None,
+                tags: tags.clone(),
+                position: (table::row_line(&examples.table, n), examples.position.1),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use gherkin::Feature;
+
+    use super::expand;
+
+    fn scenarios(source: &str) -> Vec<gherkin::Scenario> {
+        let feature = Feature::try_from(source).unwrap();
+        feature.scenarios.iter().flat_map(expand).collect()
+    }
+
+    #[test]
+    fn expands_an_outline_into_a_scenario_per_row() {
+        let scenarios = scenarios(
+            "\
+Feature: Outlines
+
+  @outline
+  Scenario Outline: eating <food>
+    Given I have <count> <food>
+      | food   |
+      | <food> |
+
+    @examples
+    Examples:
+      | food   | count |
+      | apples | 3     |
+      | pears  | 1     |
+",
+        );
+
+        assert_eq!(scenarios.len(), 2);
+        assert_eq!(scenarios[0].name, "eating apples (example 1)");
+        assert_eq!(scenarios[1].name, "eating pears (example 2)");
+        assert_eq!(scenarios[0].position.0, 12);
+        assert_eq!(scenarios[1].position.0, 13);
+        assert_eq!(scenarios[1].steps[0].value, "I have 1 pears");
+        assert_eq!(
+            scenarios[1].steps[0].table.as_ref().unwrap().rows,
+            vec![vec!["pears".to_string()]]
+        );
+        // The step keeps its place in the outline.
+        assert_eq!(scenarios[1].steps[0].position.0, 5);
+        assert_eq!(
+            scenarios[0].tags,
+            Some(vec!["@outline".to_string(), "@examples".to_string()])
+        );
+        assert!(scenarios.iter().all(|s| s.examples.is_none()));
+    }
+
+    #[test]
+    fn leaves_scenarios_without_examples_as_they_are() {
+        let scenarios = scenarios(
+            "\
+Feature: Outlines
+
+  Scenario: plain <text>
+    Given a <step>
+",
+        );
+
+        assert_eq!(scenarios.len(), 1);
+        assert_eq!(scenarios[0].name, "plain <text>");
+        assert_eq!(scenarios[0].steps[0].value, "a <step>");
+    }
+}
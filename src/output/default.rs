@@ -1,14 +1,16 @@
 use std;
 use std::collections::HashMap;
-use std::env;
-use std::io::Write;
+use std::io::{self, IsTerminal, Write};
 use std::path::Path;
+use std::time::Duration;
 
 use gherkin;
-use pathdiff::diff_paths;
 use termcolor::{Color, ColorChoice, ColorSpec, StandardStream, WriteColor};
 use textwrap;
 
+use super::{format_duration, relpath, rerun_command, OutputConfig, PendingLogs, Verbosity};
+use crate::DocstringExt;
+use crate::LogRecord;
 use crate::OutputVisitor;
 use crate::TestResult;
 
@@ -28,12 +30,37 @@ pub struct DefaultOutput {
     step_count: u32,
     skipped_count: u32,
     fail_count: u32,
+    verbosity: Verbosity,
+    // The width to wrap at, or `None` not to wrap.
+    width: Option<usize>,
+    failed: Vec<FailedScenario>,
+    step_logs: PendingLogs,
+}
+
+struct FailedScenario {
+    path: String,
+    line: usize,
+    name: String,
 }
 
 impl std::default::Default for DefaultOutput {
     fn default() -> DefaultOutput {
+        DefaultOutput::new(&OutputConfig::default())
+    }
+}
+
+impl DefaultOutput {
+    /// Creates a `DefaultOutput` writing to stdout. When `Quiet`, passing
+    /// steps are left out.
+    pub fn new(config: &OutputConfig) -> DefaultOutput {
+        let color_choice = if config.color.use_color() {
+            ColorChoice::Always
+        } else {
+            ColorChoice::Never
+        };
+
         DefaultOutput {
-            stdout: StandardStream::stdout(ColorChoice::Always),
+            stdout: StandardStream::stdout(color_choice),
             cur_feature: "".to_string(),
             feature_count: 0,
             feature_error_count: 0,
@@ -42,18 +69,33 @@ impl std::default::Default for DefaultOutput {
             step_count: 0,
             skipped_count: 0,
             fail_count: 0,
+            verbosity: config.verbosity,
+            width: match config.width {
+                Some(0) => None,
+                Some(width) => Some(width),
+                None if io::stdout().is_terminal() => Some(textwrap::termwidth()),
+                None => None,
+            },
+            failed: vec![],
+            step_logs: PendingLogs::default(),
         }
     }
 }
 
-fn wrap_with_comment(s: &str, c: &str, indent: &str) -> String {
-    let tw = textwrap::termwidth();
-    let w = tw - indent.chars().count();
+fn wrap_with_comment(s: &str, c: &str, indent: &str, width: Option<usize>) -> String {
+    let tw = match width {
+        Some(tw) => tw,
+        None => return format!("{}{}", indent, s),
+    };
+    let w = tw.saturating_sub(indent.chars().count()).max(1);
     let mut cs: Vec<String> = textwrap::wrap_iter(s, w)
         .map(|x| format!("{}{}", indent, &x.trim()))
         .collect();
+    if cs.is_empty() {
+        cs.push(indent.to_string());
+    }
     // Fit the comment onto the last line
-    let comment_space = tw - c.chars().count() - 2;
+    let comment_space = tw.saturating_sub(c.chars().count() + 2);
     let last_count = cs.last().unwrap().chars().count();
     if last_count > comment_space {
         cs.push(format!("{: <1$}", "", comment_space))
@@ -96,7 +138,8 @@ impl DefaultOutput {
         self.stdout
             .set_color(ColorSpec::new().set_fg(Some(c)).set_bold(bold))
             .unwrap();
-        write!(&mut self.stdout, "{}", wrap_with_comment(s, cmt, indent)).unwrap();
+        let wrapped = wrap_with_comment(s, cmt, indent, self.width);
+        write!(&mut self.stdout, "{}", wrapped).unwrap();
         self.stdout
             .set_color(ColorSpec::new().set_fg(Some(Color::White)).set_bold(false))
             .unwrap();
@@ -122,20 +165,28 @@ impl DefaultOutput {
         self.writeln_cmt(s, c, indent, Color::White, true);
     }
 
-    fn relpath(&self, target: &Path) -> std::path::PathBuf {
-        let target = target.canonicalize().expect("invalid target path");
-        diff_paths(
-            &target,
-            &env::current_dir().expect("invalid current directory"),
-        )
-        .expect("invalid target path")
+    /// `title` padded with dashes to the width, less `reserved` columns.
+    fn banner(&self, title: &str, reserved: usize) -> String {
+        match self.width {
+            Some(width) => format!("{:—<1$}", title, width.saturating_sub(reserved)),
+            None => format!("{}————", title),
+        }
+    }
+
+    /// `s` wrapped to the width and indented, for the body of a banner.
+    fn indented(&self, s: &str) -> String {
+        let filled = match self.width {
+            Some(width) => textwrap::fill(s, width.saturating_sub(4).max(1)),
+            None => s.to_string(),
+        };
+        textwrap::indent(&filled, "  ").trim_end().to_string()
     }
 
     fn print_step_extras(&mut self, step: &gherkin::Step) {
         let indent = "      ";
         if let Some(ref table) = &step.table {
             // Find largest sized item per column
-            let mut max_size: Vec<usize> = (&table.header).iter().map(|h| h.len()).collect();
+            let mut max_size: Vec<usize> = table.header.iter().map(|h| h.len()).collect();
 
             for row in &table.rows {
                 for (n, field) in row.iter().enumerate() {
@@ -146,13 +197,15 @@ impl DefaultOutput {
             }
 
             // If number print in a number way
-            let formatted_header_fields: Vec<String> = (&table.header)
+            let formatted_header_fields: Vec<String> = table
+                .header
                 .iter()
                 .enumerate()
                 .map(|(n, field)| format!(" {: <1$} ", field, max_size[n]))
                 .collect();
 
-            let formatted_row_fields: Vec<Vec<String>> = (&table.rows)
+            let formatted_row_fields: Vec<Vec<String>> = table
+                .rows
                 .iter()
                 .map(|row| {
                     row.iter()
@@ -189,13 +242,63 @@ impl DefaultOutput {
         };
 
         if let Some(ref docstring) = &step.docstring {
-            self.writeln(&format!("{}\"\"\"", indent), Color::Magenta, true);
+            let content_type = step.docstring_content_type().unwrap_or_default();
+            self.writeln(
+                &format!("{}\"\"\"{}", indent, content_type),
+                Color::Magenta,
+                true,
+            );
             println!("{}", textwrap::indent(docstring, indent).trim_end());
             self.writeln(&format!("{}\"\"\"", indent), Color::Magenta, true);
         }
     }
 
-    fn print_finish(&mut self) -> Result<(), std::io::Error> {
+    fn print_failed(&mut self) -> Result<(), std::io::Error> {
+        if self.failed.is_empty() {
+            return Ok(());
+        }
+
+        self.writeln("Failing scenarios:", Color::Red, true);
+        for failed in &self.failed {
+            self.stdout
+                .set_color(ColorSpec::new().set_fg(Some(Color::Red)))?;
+            write!(&mut self.stdout, "{}:{}", failed.path, failed.line)?;
+            self.stdout
+                .set_color(ColorSpec::new().set_fg(Some(Color::White)))?;
+            writeln!(&mut self.stdout, " # {}", failed.name)?;
+        }
+        self.stdout.set_color(ColorSpec::new().set_fg(None))?;
+        self.println("");
+
+        self.writeln("Rerun them with:", Color::White, true);
+        for failed in &self.failed {
+            let command = rerun_command(&[format!("{}:{}", failed.path, failed.line)]);
+            writeln!(&mut self.stdout, "{}", command)?;
+        }
+        self.println("");
+
+        Ok(())
+    }
+
+    fn print_logs(&mut self, records: &[LogRecord]) {
+        if records.is_empty() {
+            return;
+        }
+
+        let banner = self.banner("———— Captured logs: ", 0);
+        self.writeln(&banner, Color::Red, true);
+        let lines = records
+            .iter()
+            .map(|record| record.to_string())
+            .collect::<Vec<_>>()
+            .join("\n");
+        let captured = self.indented(&lines);
+        self.red(&captured);
+    }
+
+    fn print_finish(&mut self, duration: Duration) -> Result<(), std::io::Error> {
+        self.print_failed()?;
+
         self.set_color(Color::White, true);
 
         // Do feature count
@@ -219,26 +322,17 @@ impl DefaultOutput {
         let scenario_passed_count = self
             .scenarios
             .values()
-            .filter(|v| match v {
-                ScenarioResult::Pass => true,
-                _ => false,
-            })
+            .filter(|v| matches!(v, ScenarioResult::Pass))
             .count();
         let scenario_fail_count = self
             .scenarios
             .values()
-            .filter(|v| match v {
-                ScenarioResult::Fail => true,
-                _ => false,
-            })
+            .filter(|v| matches!(v, ScenarioResult::Fail))
             .count();
         let scenario_skipped_count = self
             .scenarios
             .values()
-            .filter(|v| match v {
-                ScenarioResult::Skip => true,
-                _ => false,
-            })
+            .filter(|v| matches!(v, ScenarioResult::Skip))
             .count();
 
         write!(&mut self.stdout, "{} scenarios (", &self.scenarios.len())?;
@@ -300,6 +394,8 @@ impl DefaultOutput {
         write!(&mut self.stdout, ")")?;
         self.println("");
 
+        self.println(&format!("Finished in {}", format_duration(duration)));
+
         self.stdout
             .set_color(ColorSpec::new().set_fg(None).set_bold(false))?;
         self.println("");
@@ -314,7 +410,7 @@ impl OutputVisitor for DefaultOutput {
     }
 
     fn visit_feature(&mut self, feature: &gherkin::Feature, path: &Path) {
-        self.cur_feature = self.relpath(&path).to_string_lossy().to_string();
+        self.cur_feature = relpath(path).to_string_lossy().to_string();
         let msg = &format!("Feature: {}", &feature.name);
         let cmt = &format!(
             "{}:{}:{}",
@@ -326,38 +422,21 @@ impl OutputVisitor for DefaultOutput {
         self.feature_count += 1;
     }
 
-    fn visit_feature_end(&mut self, _feature: &gherkin::Feature) {}
+    fn visit_feature_end(&mut self, _feature: &gherkin::Feature, _duration: Duration) {}
 
     fn visit_feature_error<'r>(&mut self, path: &Path, error: &gherkin::Error<'r>) {
         let position = gherkin::error_position(error);
-        let relpath = self.relpath(&path).to_string_lossy().to_string();
+        let relpath = relpath(path).to_string_lossy().to_string();
         let loc = &format!("{}:{}:{}", &relpath, position.0, position.1);
 
-        self.writeln_cmt(
-            &format!(
-                "{:—<1$}",
-                "! Parsing feature failed: ",
-                textwrap::termwidth() - loc.chars().count() - 7
-            ),
-            &loc,
-            "———— ",
-            Color::Red,
-            true,
-        );
+        let banner = self.banner("! Parsing feature failed: ", loc.chars().count() + 7);
+        self.writeln_cmt(&banner, loc, "———— ", Color::Red, true);
 
-        self.red(
-            &textwrap::indent(
-                &textwrap::fill(&format!("{}", error), textwrap::termwidth() - 4),
-                "  ",
-            )
-            .trim_end(),
-        );
+        let message = self.indented(&format!("{}", error));
+        self.red(&message);
 
-        self.writeln(
-            &format!("{:—<1$}\n", "", textwrap::termwidth()),
-            Color::Red,
-            true,
-        );
+        let rule = self.banner("", 0);
+        self.writeln(&format!("{}\n", rule), Color::Red, true);
 
         self.feature_error_count += 1;
     }
@@ -394,11 +473,24 @@ impl OutputVisitor for DefaultOutput {
         }
     }
 
-    fn visit_scenario_end(&mut self, _rule: Option<&gherkin::Rule>, scenario: &gherkin::Scenario) {
+    fn visit_scenario_end(
+        &mut self,
+        rule: Option<&gherkin::Rule>,
+        scenario: &gherkin::Scenario,
+        duration: Duration,
+    ) {
         if !self.scenarios.contains_key(scenario) {
             self.scenarios
                 .insert(scenario.clone(), ScenarioResult::Pass);
         }
+        if self.verbosity == Verbosity::Verbose {
+            let indent = if rule.is_some() { "   " } else { "  " };
+            self.writeln(
+                &format!("{}Took {}", indent, format_duration(duration)),
+                Color::White,
+                false,
+            );
+        }
         self.println("");
     }
 
@@ -411,103 +503,94 @@ impl OutputVisitor for DefaultOutput {
         self.step_count += 1;
     }
 
+    fn visit_logs(
+        &mut self,
+        _rule: Option<&gherkin::Rule>,
+        scenario: &gherkin::Scenario,
+        step: Option<&gherkin::Step>,
+        records: &[LogRecord],
+    ) {
+        if step.is_some() {
+            self.step_logs.hold(records);
+            return;
+        }
+
+        // Records from outside the steps are shown only for failed scenarios.
+        if let Some(ScenarioResult::Fail) = self.scenarios.get(scenario) {
+            self.print_logs(records);
+            let rule = self.banner("", 0);
+            self.writeln(&rule, Color::Red, true);
+        }
+    }
+
     fn visit_step_result(
         &mut self,
         rule: Option<&gherkin::Rule>,
         scenario: &gherkin::Scenario,
         step: &gherkin::Step,
         result: &TestResult,
+        duration: Duration,
     ) {
-        let cmt = &format!(
+        let logs = self.step_logs.take();
+        let mut cmt = format!(
             "{}:{}:{}",
             &self.cur_feature, step.position.0, step.position.1
         );
+        if self.verbosity == Verbosity::Verbose
+            && matches!(result, TestResult::Pass | TestResult::Fail(_, _, _))
+        {
+            cmt.push_str(&format!(" ({})", format_duration(duration)));
+        }
+        let cmt = &cmt;
         let msg = &step.to_string();
         let indent = if rule.is_some() { "   " } else { "  " };
 
         match result {
             TestResult::Pass => {
-                self.writeln_cmt(&format!("✔ {}", msg), cmt, indent, Color::Green, false);
-                self.print_step_extras(step);
+                if self.verbosity > Verbosity::Quiet {
+                    self.writeln_cmt(&format!("✔ {}", msg), cmt, indent, Color::Green, false);
+                    self.print_step_extras(step);
+                }
             }
             TestResult::Fail(panic_info, captured_stdout, captured_stderr) => {
                 self.writeln_cmt(&format!("✘ {}", msg), cmt, indent, Color::Red, false);
                 self.print_step_extras(step);
-                self.writeln_cmt(
-                    &format!(
-                        "{:—<1$}",
-                        "! Step failed: ",
-                        textwrap::termwidth() - panic_info.location.chars().count() - 7
-                    ),
-                    &panic_info.location,
-                    "———— ",
-                    Color::Red,
-                    true,
-                );
-                self.red(
-                    &textwrap::indent(
-                        &textwrap::fill(&panic_info.payload, textwrap::termwidth() - 4),
-                        "  ",
-                    )
-                    .trim_end(),
-                );
+                let banner =
+                    self.banner("! Step failed: ", panic_info.location.chars().count() + 7);
+                self.writeln_cmt(&banner, &panic_info.location, "———— ", Color::Red, true);
+                let payload = self.indented(&panic_info.payload);
+                self.red(&payload);
 
                 if !captured_stdout.is_empty() {
-                    self.writeln(
-                        &format!(
-                            "{:—<1$}",
-                            "———— Captured stdout: ",
-                            textwrap::termwidth()
-                        ),
-                        Color::Red,
-                        true,
-                    );
-                    self.red(
-                        &textwrap::indent(
-                            &textwrap::fill(
-                                &String::from_utf8_lossy(captured_stderr),
-                                textwrap::termwidth() - 4,
-                            ),
-                            "  ",
-                        )
-                        .trim_end(),
-                    );
+                    let banner = self.banner("———— Captured stdout: ", 0);
+                    self.writeln(&banner, Color::Red, true);
+                    let captured = self.indented(&String::from_utf8_lossy(captured_stdout));
+                    self.red(&captured);
                 }
 
                 if !captured_stderr.is_empty() {
-                    self.writeln(
-                        &format!(
-                            "{:—<1$}",
-                            "———— Captured stderr: ",
-                            textwrap::termwidth()
-                        ),
-                        Color::Red,
-                        true,
-                    );
-                    self.red(
-                        &textwrap::indent(
-                            &textwrap::fill(
-                                &String::from_utf8_lossy(captured_stderr),
-                                textwrap::termwidth() - 4,
-                            ),
-                            "  ",
-                        )
-                        .trim_end(),
-                    );
+                    let banner = self.banner("———— Captured stderr: ", 0);
+                    self.writeln(&banner, Color::Red, true);
+                    let captured = self.indented(&String::from_utf8_lossy(captured_stderr));
+                    self.red(&captured);
                 }
 
-                self.writeln(
-                    &format!("{:—<1$}", "", textwrap::termwidth()),
-                    Color::Red,
-                    true,
-                );
+                self.print_logs(&logs);
+
+                let rule = self.banner("", 0);
+                self.writeln(&rule, Color::Red, true);
 
                 self.fail_count += 1;
                 self.scenarios
                     .insert(scenario.clone(), ScenarioResult::Fail);
+                self.failed.push(FailedScenario {
+                    path: self.cur_feature.clone(),
+                    line: scenario.position.0,
+                    name: scenario.name.clone(),
+                });
             }
             TestResult::MutexPoisoned => {
-                self.writeln_cmt(&format!("- {}", msg), cmt, indent, Color::Cyan, false);
+                self.writeln_cmt(&format!("! {}", msg), cmt, indent, Color::Cyan, false);
                 self.print_step_extras(step);
                 self.write(&format!("{}  ⚡ ", indent), Color::Yellow, false);
                 self.println("Skipped due to previous error (poisoned)");
@@ -519,7 +602,7 @@ impl OutputVisitor for DefaultOutput {
                 self.skipped_count += 1;
             }
             TestResult::Unimplemented => {
-                self.writeln_cmt(&format!("- {}", msg), cmt, indent, Color::Cyan, false);
+                self.writeln_cmt(&format!("? {}", msg), cmt, indent, Color::Cyan, false);
                 self.print_step_extras(step);
                 self.write(&format!("{}  ⚡ ", indent), Color::Yellow, false);
                 self.println("Not yet implemented (skipped)");
@@ -529,7 +612,7 @@ impl OutputVisitor for DefaultOutput {
         };
     }
 
-    fn visit_finish(&mut self) {
-        self.print_finish().unwrap();
+    fn visit_finish(&mut self, duration: Duration) {
+        self.print_finish(duration).unwrap();
     }
 }
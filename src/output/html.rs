@@ -0,0 +1,541 @@
+use std::collections::BTreeSet;
+use std::io::{self, Write};
+use std::path::Path;
+use std::time::Duration;
+
+use gherkin;
+
+use super::{escape, format_duration, relpath, PendingLogs, ReportOutput};
+use crate::DocstringExt;
+use crate::LogRecord;
+use crate::OutputVisitor;
+use crate::TestResult;
+
+const STYLE: &str = r#"
+body { font-family: -apple-system, "Segoe UI", Helvetica, Arial, sans-serif; margin: 2em; color: #222; }
+h1 { font-size: 1.5em; }
+.controls { margin-bottom: 1.5em; }
+.controls label { margin-right: 1em; }
+.summary span { margin-right: 1em; }
+details { margin: 0.25em 0; }
+summary { cursor: pointer; padding: 0.25em 0.5em; border-radius: 3px; }
+.feature > summary { font-size: 1.15em; font-weight: bold; background: #eee; }
+.scenario { margin-left: 1.5em; }
+.steps { margin: 0.25em 0 0.5em 2em; }
+.step { padding: 0.1em 0; }
+.location, .duration, .tag { color: #777; font-size: 0.85em; }
+.tag { margin-left: 0.5em; }
+.passed > summary, .step.passed .status { color: #1a7f37; }
+.failed > summary, .step.failed .status { color: #cf222e; }
+.skipped > summary, .step.skipped .status { color: #9a6700; }
+.undefined > summary, .step.undefined .status { color: #8250df; }
+pre { background: #f6f8fa; padding: 0.5em; margin: 0.25em 0; overflow-x: auto; }
+pre.error { background: #ffebe9; }
+table { border-collapse: collapse; margin: 0.25em 0; }
+td { border: 1px solid #ccc; padding: 0.1em 0.5em; }
+.hidden { display: none; }
+"#;
+
+const SCRIPT: &str = r#"
+function applyFilters() {
+  var status = document.getElementById("status-filter").value;
+  var tag = document.getElementById("tag-filter").value;
+  document.querySelectorAll(".feature").forEach(function (feature) {
+    var visible = 0;
+    feature.querySelectorAll(".scenario").forEach(function (scenario) {
+      var tags = scenario.dataset.tags.split(" ");
+      var show = (status === "" || scenario.dataset.status === status) &&
+        (tag === "" || tags.indexOf(tag) !== -1);
+      scenario.classList.toggle("hidden", !show);
+      if (show) { visible += 1; }
+    });
+    feature.classList.toggle("hidden", visible === 0);
+  });
+}
+
+function setOpen(open) {
+  document.querySelectorAll("details").forEach(function (details) { details.open = open; });
+}
+
+document.getElementById("status-filter").addEventListener("change", applyFilters);
+document.getElementById("tag-filter").addEventListener("change", applyFilters);
+document.getElementById("expand-all").addEventListener("click", function () { setOpen(true); });
+document.getElementById("collapse-all").addEventListener("click", function () { setOpen(false); });
+"#;
+
+/// Writes results as a single, self-contained HTML page, with collapsible
+/// features and scenarios that can be filtered by tag and by status.
+///
+/// The page opens with totals across all the scenarios, so it is only
+/// written at the end of the run.
+pub struct HtmlOutput {
+    writer: Box<dyn Write>,
+    features: Vec<Feature>,
+    errors: Vec<String>,
+    total: Duration,
+    step_logs: PendingLogs,
+}
+
+struct Feature {
+    name: String,
+    uri: String,
+    tags: Vec<String>,
+    scenarios: Vec<Scenario>,
+}
+
+struct Scenario {
+    name: String,
+    rule: Option<String>,
+    line: usize,
+    tags: Vec<String>,
+    duration: Duration,
+    steps: Vec<Step>,
+    // The records logged outside of its steps, one per line.
+    logs: String,
+}
+
+struct Step {
+    keyword: String,
+    text: String,
+    line: usize,
+    status: Status,
+    duration: Option<Duration>,
+    docstring: Option<(String, Option<String>)>,
+    table: Option<gherkin::Table>,
+    error: Option<String>,
+    stdout: String,
+    stderr: String,
+    logs: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Status {
+    Passed,
+    Skipped,
+    Undefined,
+    Failed,
+}
+
+impl Status {
+    fn as_str(self) -> &'static str {
+        match self {
+            Status::Passed => "passed",
+            Status::Skipped => "skipped",
+            Status::Undefined => "undefined",
+            Status::Failed => "failed",
+        }
+    }
+
+    fn symbol(self) -> &'static str {
+        match self {
+            Status::Passed => "✔",
+            Status::Skipped => "↷",
+            Status::Undefined => "?",
+            Status::Failed => "✘",
+        }
+    }
+}
+
+impl Scenario {
+    // The worst status of any of its steps.
+    fn status(&self) -> Status {
+        self.steps
+            .iter()
+            .map(|step| step.status)
+            .max()
+            .unwrap_or(Status::Passed)
+    }
+}
+
+impl ReportOutput for HtmlOutput {
+    fn new(writer: Box<dyn Write>) -> HtmlOutput {
+        HtmlOutput {
+            writer,
+            features: vec![],
+            errors: vec![],
+            total: Duration::default(),
+            step_logs: PendingLogs::default(),
+        }
+    }
+}
+
+impl HtmlOutput {
+    fn current_scenario(&mut self) -> &mut Scenario {
+        self.features
+            .last_mut()
+            .and_then(|feature| feature.scenarios.last_mut())
+            .expect("step visited outside of a scenario")
+    }
+
+    fn write_report(&mut self) -> io::Result<()> {
+        let total = self.total;
+        let HtmlOutput {
+            writer: w,
+            features,
+            errors,
+            ..
+        } = self;
+        let scenarios = || features.iter().flat_map(|f| f.scenarios.iter());
+
+        let tags = scenarios()
+            .flat_map(|s| s.tags.iter())
+            .collect::<BTreeSet<_>>();
+
+        writeln!(w, "<!DOCTYPE html>")?;
+        writeln!(w, r#"<html lang="en">"#)?;
+        writeln!(w, "<head>")?;
+        writeln!(w, r#"<meta charset="utf-8">"#)?;
+        writeln!(w, "<title>Cucumber report</title>")?;
+        writeln!(w, "<style>{}</style>", STYLE)?;
+        writeln!(w, "</head>")?;
+        writeln!(w, "<body>")?;
+        writeln!(w, "<h1>Cucumber report</h1>")?;
+
+        write!(w, r#"<p class="summary">"#)?;
+        write!(w, "<span>{} scenarios</span>", scenarios().count())?;
+        for status in &[
+            Status::Passed,
+            Status::Failed,
+            Status::Skipped,
+            Status::Undefined,
+        ] {
+            write!(
+                w,
+                r#"<span class="{0}">{1} {0}</span>"#,
+                status.as_str(),
+                scenarios().filter(|s| s.status() == *status).count()
+            )?;
+        }
+        writeln!(
+            w,
+            r#"<span class="duration">{}</span></p>"#,
+            format_duration(total)
+        )?;
+
+        writeln!(w, r#"<div class="controls">"#)?;
+        writeln!(
+            w,
+            r#"<label>Status <select id="status-filter"><option value="">All</option><option value="passed">Passed</option><option value="failed">Failed</option><option value="skipped">Skipped</option><option value="undefined">Undefined</option></select></label>"#
+        )?;
+        write!(
+            w,
+            r#"<label>Tag <select id="tag-filter"><option value="">All</option>"#
+        )?;
+        for tag in tags {
+            write!(w, r#"<option value="{0}">{0}</option>"#, escape(tag))?;
+        }
+        writeln!(w, "</select></label>")?;
+        writeln!(
+            w,
+            r#"<button id="expand-all">Expand all</button> <button id="collapse-all">Collapse all</button>"#
+        )?;
+        writeln!(w, "</div>")?;
+
+        for error in errors.iter() {
+            writeln!(w, r#"<pre class="error">{}</pre>"#, escape(error))?;
+        }
+
+        for feature in features.iter() {
+            let status = feature
+                .scenarios
+                .iter()
+                .map(Scenario::status)
+                .max()
+                .unwrap_or(Status::Passed);
+
+            writeln!(w, r#"<details class="feature {}" open>"#, status.as_str())?;
+            write!(w, "<summary>Feature: {}", escape(&feature.name))?;
+            for tag in &feature.tags {
+                write!(w, r#"<span class="tag">{}</span>"#, escape(tag))?;
+            }
+            writeln!(
+                w,
+                r#" <span class="location">{}</span></summary>"#,
+                escape(&feature.uri)
+            )?;
+
+            for scenario in &feature.scenarios {
+                let status = scenario.status();
+                writeln!(
+                    w,
+                    r#"<details class="scenario {}" data-status="{}" data-tags="{}"{}>"#,
+                    status.as_str(),
+                    status.as_str(),
+                    escape(&scenario.tags.join(" ")),
+                    if status == Status::Failed {
+                        " open"
+                    } else {
+                        ""
+                    }
+                )?;
+                write!(w, "<summary>{} ", status.symbol())?;
+                if let Some(rule) = &scenario.rule {
+                    write!(w, "Rule: {} / ", escape(rule))?;
+                }
+                write!(w, "Scenario: {}", escape(&scenario.name))?;
+                for tag in &scenario.tags {
+                    write!(w, r#"<span class="tag">{}</span>"#, escape(tag))?;
+                }
+                writeln!(
+                    w,
+                    r#" <span class="location">{}:{}</span> <span class="duration">{}</span></summary>"#,
+                    escape(&feature.uri),
+                    scenario.line,
+                    format_duration(scenario.duration)
+                )?;
+
+                writeln!(w, r#"<div class="steps">"#)?;
+                for step in &scenario.steps {
+                    write_step(w, &feature.uri, step)?;
+                }
+                write_logs(w, &scenario.logs)?;
+                writeln!(w, "</div>")?;
+                writeln!(w, "</details>")?;
+            }
+
+            writeln!(w, "</details>")?;
+        }
+
+        writeln!(w, "<script>{}</script>", SCRIPT)?;
+        writeln!(w, "</body>")?;
+        writeln!(w, "</html>")?;
+        w.flush()
+    }
+}
+
+fn write_step(w: &mut dyn Write, uri: &str, step: &Step) -> io::Result<()> {
+    writeln!(w, r#"<div class="step {}">"#, step.status.as_str())?;
+    write!(
+        w,
+        r#"<span class="status">{}</span> <b>{}</b> {} <span class="location">{}:{}</span>"#,
+        step.status.symbol(),
+        escape(&step.keyword),
+        escape(&step.text),
+        escape(uri),
+        step.line
+    )?;
+    if let Some(duration) = step.duration {
+        write!(
+            w,
+            r#" <span class="duration">{}</span>"#,
+            format_duration(duration)
+        )?;
+    }
+    writeln!(w)?;
+
+    if let Some((docstring, content_type)) = &step.docstring {
+        writeln!(
+            w,
+            r#"<pre class="docstring" title="{}">{}</pre>"#,
+            escape(content_type.as_deref().unwrap_or_default()),
+            escape(docstring)
+        )?;
+    }
+
+    if let Some(table) = &step.table {
+        writeln!(w, "<table>")?;
+        for row in std::iter::once(&table.header).chain(table.rows.iter()) {
+            write!(w, "<tr>")?;
+            for cell in row {
+                write!(w, "<td>{}</td>", escape(cell))?;
+            }
+            writeln!(w, "</tr>")?;
+        }
+        writeln!(w, "</table>")?;
+    }
+
+    if let Some(error) = &step.error {
+        writeln!(w, r#"<pre class="error">{}</pre>"#, escape(error))?;
+    }
+
+    if !step.stdout.is_empty() {
+        writeln!(
+            w,
+            "<details><summary>Captured stdout</summary><pre>{}</pre></details>",
+            escape(&step.stdout)
+        )?;
+    }
+
+    if !step.stderr.is_empty() {
+        writeln!(
+            w,
+            "<details><summary>Captured stderr</summary><pre>{}</pre></details>",
+            escape(&step.stderr)
+        )?;
+    }
+
+    write_logs(w, &step.logs)?;
+
+    writeln!(w, "</div>")
+}
+
+fn write_logs(w: &mut dyn Write, logs: &str) -> io::Result<()> {
+    if logs.is_empty() {
+        return Ok(());
+    }
+
+    writeln!(
+        w,
+        "<details><summary>Captured logs</summary><pre>{}</pre></details>",
+        escape(logs)
+    )
+}
+
+fn log_lines(records: &[LogRecord]) -> String {
+    records
+        .iter()
+        .map(|record| format!("{}\n", record))
+        .collect()
+}
+
+impl OutputVisitor for HtmlOutput {
+    fn visit_start(&mut self) {}
+
+    fn visit_feature(&mut self, feature: &gherkin::Feature, path: &Path) {
+        self.features.push(Feature {
+            name: feature.name.clone(),
+            uri: relpath(path).to_string_lossy().to_string(),
+            tags: feature.tags.clone().unwrap_or_default(),
+            scenarios: vec![],
+        });
+    }
+
+    fn visit_feature_end(&mut self, _feature: &gherkin::Feature, _duration: Duration) {}
+
+    fn visit_feature_error<'a>(&mut self, path: &Path, error: &gherkin::Error<'a>) {
+        let position = gherkin::error_position(error);
+        self.errors.push(format!(
+            "Parsing feature failed: {}:{}:{}\n{}",
+            relpath(path).to_string_lossy(),
+            position.0,
+            position.1,
+            error
+        ));
+    }
+
+    fn visit_rule(&mut self, _rule: &gherkin::Rule) {}
+
+    fn visit_rule_end(&mut self, _rule: &gherkin::Rule) {}
+
+    fn visit_scenario(&mut self, rule: Option<&gherkin::Rule>, scenario: &gherkin::Scenario) {
+        let feature = self
+            .features
+            .last_mut()
+            .expect("scenario visited outside of a feature");
+
+        // Scenarios inherit the tags of their feature and rule.
+        let tags = feature
+            .tags
+            .iter()
+            .chain(
+                rule.and_then(|rule| rule.tags.as_ref())
+                    .into_iter()
+                    .flatten(),
+            )
+            .chain(scenario.tags.iter().flatten())
+            .cloned()
+            .collect();
+
+        feature.scenarios.push(Scenario {
+            name: scenario.name.clone(),
+            rule: rule.map(|rule| rule.name.clone()),
+            line: scenario.position.0,
+            tags,
+            duration: Duration::default(),
+            steps: vec![],
+            logs: String::new(),
+        });
+    }
+
+    fn visit_scenario_end(
+        &mut self,
+        _rule: Option<&gherkin::Rule>,
+        _scenario: &gherkin::Scenario,
+        duration: Duration,
+    ) {
+        self.current_scenario().duration = duration;
+    }
+
+    fn visit_scenario_skipped(
+        &mut self,
+        _rule: Option<&gherkin::Rule>,
+        _scenario: &gherkin::Scenario,
+    ) {
+    }
+
+    fn visit_step(
+        &mut self,
+        _rule: Option<&gherkin::Rule>,
+        _scenario: &gherkin::Scenario,
+        _step: &gherkin::Step,
+    ) {
+    }
+
+    fn visit_logs(
+        &mut self,
+        _rule: Option<&gherkin::Rule>,
+        _scenario: &gherkin::Scenario,
+        step: Option<&gherkin::Step>,
+        records: &[LogRecord],
+    ) {
+        match step {
+            Some(_) => self.step_logs.hold(records),
+            None => self.current_scenario().logs = log_lines(records),
+        }
+    }
+
+    fn visit_step_result(
+        &mut self,
+        _rule: Option<&gherkin::Rule>,
+        _scenario: &gherkin::Scenario,
+        step: &gherkin::Step,
+        result: &TestResult,
+        duration: Duration,
+    ) {
+        let elapsed = Some(duration);
+
+        let (status, duration, error, stdout, stderr) = match result {
+            TestResult::Pass => (Status::Passed, elapsed, None, "".into(), "".into()),
+            TestResult::Fail(panic_info, stdout, stderr) => (
+                Status::Failed,
+                elapsed,
+                Some(format!("{}\n{}", panic_info.payload, panic_info.location)),
+                String::from_utf8_lossy(stdout),
+                String::from_utf8_lossy(stderr),
+            ),
+            TestResult::MutexPoisoned => (
+                Status::Failed,
+                None,
+                Some("Skipped due to previous error (poisoned)".to_string()),
+                "".into(),
+                "".into(),
+            ),
+            TestResult::Skipped => (Status::Skipped, None, None, "".into(), "".into()),
+            TestResult::Unimplemented => (Status::Undefined, None, None, "".into(), "".into()),
+        };
+
+        let html_step = Step {
+            keyword: step.raw_type.clone(),
+            text: step.value.clone(),
+            line: step.position.0,
+            status,
+            duration,
+            docstring: step
+                .docstring
+                .clone()
+                .map(|docstring| (docstring, step.docstring_content_type())),
+            table: step.table.clone(),
+            error,
+            stdout: stdout.to_string(),
+            stderr: stderr.to_string(),
+            logs: log_lines(&self.step_logs.take()),
+        };
+
+        self.current_scenario().steps.push(html_step);
+    }
+
+    fn visit_finish(&mut self, duration: Duration) {
+        self.total = duration;
+        self.write_report().expect("failed to write HTML report");
+    }
+}
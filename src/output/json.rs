@@ -0,0 +1,357 @@
+use std::collections::HashSet;
+use std::io::Write;
+use std::path::Path;
+use std::time::Duration;
+
+use base64::Engine;
+use gherkin;
+use serde::Serialize;
+
+use super::{relpath, PendingLogs, ReportOutput};
+use crate::table;
+use crate::DocstringExt;
+use crate::LogRecord;
+use crate::OutputVisitor;
+use crate::TestResult;
+
+/// Writes results in the Cucumber JSON format understood by most Cucumber
+/// reporting tools.
+///
+/// The report is a single array of every feature, so nothing is written
+/// until the last of them has been run.
+pub struct JsonOutput {
+    writer: Box<dyn Write>,
+    features: Vec<Feature>,
+    background: Option<gherkin::Background>,
+    background_index: Option<usize>,
+    // The lines of the rows of examples of the feature's outlines, which the
+    // scenarios expanded from them are positioned at.
+    outline_rows: HashSet<usize>,
+    step_logs: PendingLogs,
+}
+
+impl ReportOutput for JsonOutput {
+    fn new(writer: Box<dyn Write>) -> JsonOutput {
+        JsonOutput {
+            writer,
+            features: vec![],
+            background: None,
+            background_index: None,
+            outline_rows: HashSet::new(),
+            step_logs: PendingLogs::default(),
+        }
+    }
+}
+
+impl JsonOutput {
+    fn current_feature(&mut self) -> &mut Feature {
+        self.features
+            .last_mut()
+            .expect("scenario visited outside of a feature")
+    }
+}
+
+#[derive(Serialize)]
+struct Feature {
+    uri: String,
+    id: String,
+    keyword: &'static str,
+    name: String,
+    description: String,
+    line: usize,
+    tags: Vec<Tag>,
+    elements: Vec<Element>,
+}
+
+#[derive(Serialize)]
+struct Tag {
+    name: String,
+}
+
+#[derive(Serialize)]
+struct Element {
+    id: String,
+    keyword: &'static str,
+    #[serde(rename = "type")]
+    ty: &'static str,
+    name: String,
+    description: String,
+    line: usize,
+    tags: Vec<Tag>,
+    steps: Vec<Step>,
+}
+
+#[derive(Serialize)]
+struct Step {
+    keyword: String,
+    name: String,
+    line: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    doc_string: Option<DocString>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    rows: Vec<Row>,
+    result: StepResult,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    embeddings: Vec<Embedding>,
+}
+
+#[derive(Serialize)]
+struct DocString {
+    value: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    content_type: String,
+    line: usize,
+}
+
+#[derive(Serialize)]
+struct Row {
+    cells: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct StepResult {
+    status: &'static str,
+    /// In nanoseconds.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    duration: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error_message: Option<String>,
+}
+
+#[derive(Serialize)]
+struct Embedding {
+    data: String,
+    mime_type: &'static str,
+}
+
+fn slug(name: &str) -> String {
+    name.to_lowercase()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+fn tags(tags: &Option<Vec<String>>) -> Vec<Tag> {
+    tags.iter()
+        .flatten()
+        .map(|name| Tag { name: name.clone() })
+        .collect()
+}
+
+fn embedding(captured: &[u8]) -> Embedding {
+    Embedding {
+        data: base64::engine::general_purpose::STANDARD.encode(captured),
+        mime_type: "text/plain",
+    }
+}
+
+impl OutputVisitor for JsonOutput {
+    fn visit_start(&mut self) {}
+
+    fn visit_feature(&mut self, feature: &gherkin::Feature, path: &Path) {
+        self.background = feature.background.clone();
+        self.outline_rows = feature
+            .scenarios
+            .iter()
+            .chain(feature.rules.iter().flat_map(|rule| rule.scenarios.iter()))
+            .filter_map(|scenario| scenario.examples.as_ref())
+            .flat_map(|examples| {
+                (0..examples.table.rows.len()).map(move |n| table::row_line(&examples.table, n))
+            })
+            .collect();
+        self.features.push(Feature {
+            uri: relpath(path).to_string_lossy().to_string(),
+            id: slug(&feature.name),
+            keyword: "Feature",
+            name: feature.name.clone(),
+            description: feature.description.clone().unwrap_or_default(),
+            line: feature.position.0,
+            tags: tags(&feature.tags),
+            elements: vec![],
+        });
+    }
+
+    fn visit_feature_end(&mut self, _feature: &gherkin::Feature, _duration: Duration) {}
+
+    fn visit_feature_error<'a>(&mut self, _path: &Path, _error: &gherkin::Error<'a>) {}
+
+    fn visit_rule(&mut self, _rule: &gherkin::Rule) {}
+
+    fn visit_rule_end(&mut self, _rule: &gherkin::Rule) {}
+
+    fn visit_scenario(&mut self, _rule: Option<&gherkin::Rule>, scenario: &gherkin::Scenario) {
+        let background = self.background.clone();
+        let is_outline_row = self.outline_rows.contains(&scenario.position.0);
+        let feature = self.current_feature();
+        let id = format!("{};{}", feature.id, slug(&scenario.name));
+
+        // Background steps are run as part of every scenario, and reported as
+        // a background element preceding it.
+        if let Some(background) = background {
+            feature.elements.push(Element {
+                id: id.clone(),
+                keyword: "Background",
+                ty: "background",
+                name: "".to_string(),
+                description: "".to_string(),
+                line: background.position.0,
+                tags: vec![],
+                steps: vec![],
+            });
+        }
+
+        feature.elements.push(Element {
+            id,
+            keyword: if is_outline_row {
+                "Scenario Outline"
+            } else {
+                "Scenario"
+            },
+            ty: "scenario",
+            name: scenario.name.clone(),
+            description: "".to_string(),
+            line: scenario.position.0,
+            tags: tags(&scenario.tags),
+            steps: vec![],
+        });
+
+        let elements = feature.elements.len();
+        self.background_index = self.background.as_ref().map(|_| elements - 2);
+    }
+
+    fn visit_scenario_end(
+        &mut self,
+        _rule: Option<&gherkin::Rule>,
+        _scenario: &gherkin::Scenario,
+        _duration: Duration,
+    ) {
+    }
+
+    fn visit_scenario_skipped(
+        &mut self,
+        _rule: Option<&gherkin::Rule>,
+        _scenario: &gherkin::Scenario,
+    ) {
+    }
+
+    fn visit_step(
+        &mut self,
+        _rule: Option<&gherkin::Rule>,
+        _scenario: &gherkin::Scenario,
+        _step: &gherkin::Step,
+    ) {
+    }
+
+    fn visit_logs(
+        &mut self,
+        _rule: Option<&gherkin::Rule>,
+        _scenario: &gherkin::Scenario,
+        step: Option<&gherkin::Step>,
+        records: &[LogRecord],
+    ) {
+        // Cucumber JSON has nowhere for records from outside the steps.
+        if step.is_some() {
+            self.step_logs.hold(records);
+        }
+    }
+
+    fn visit_step_result(
+        &mut self,
+        _rule: Option<&gherkin::Rule>,
+        _scenario: &gherkin::Scenario,
+        step: &gherkin::Step,
+        result: &TestResult,
+        duration: Duration,
+    ) {
+        let duration = Some(duration.as_nanos() as u64);
+
+        let step_result = match result {
+            TestResult::Pass => StepResult {
+                status: "passed",
+                duration,
+                error_message: None,
+            },
+            TestResult::Fail(panic_info, _, _) => StepResult {
+                status: "failed",
+                duration,
+                error_message: Some(format!("{}\n{}", panic_info.payload, panic_info.location)),
+            },
+            TestResult::MutexPoisoned => StepResult {
+                status: "failed",
+                duration: None,
+                error_message: Some("Skipped due to previous error (poisoned)".to_string()),
+            },
+            TestResult::Skipped => StepResult {
+                status: "skipped",
+                duration: None,
+                error_message: None,
+            },
+            TestResult::Unimplemented => StepResult {
+                status: "undefined",
+                duration: None,
+                error_message: None,
+            },
+        };
+
+        let mut embeddings = match result {
+            TestResult::Fail(_, stdout, stderr) => [stdout, stderr]
+                .iter()
+                .filter(|captured| !captured.is_empty())
+                .map(|captured| embedding(captured))
+                .collect(),
+            _ => vec![],
+        };
+        let logs = self.step_logs.take();
+        if !logs.is_empty() {
+            let lines = logs
+                .iter()
+                .map(|record| format!("{}\n", record))
+                .collect::<String>();
+            embeddings.push(embedding(lines.as_bytes()));
+        }
+
+        let json_step = Step {
+            keyword: format!("{} ", step.raw_type),
+            name: step.value.clone(),
+            line: step.position.0,
+            doc_string: step.docstring.as_ref().map(|value| DocString {
+                value: value.clone(),
+                content_type: step.docstring_content_type().unwrap_or_default(),
+                line: step.position.0 + 1,
+            }),
+            rows: step
+                .table
+                .iter()
+                .flat_map(|table| std::iter::once(&table.header).chain(table.rows.iter()))
+                .map(|cells| Row {
+                    cells: cells.clone(),
+                })
+                .collect(),
+            result: step_result,
+            embeddings,
+        };
+
+        let is_background_step = self
+            .background
+            .as_ref()
+            .map(|background| background.steps.contains(step))
+            .unwrap_or(false);
+        let background_index = self.background_index;
+
+        let elements = &mut self.current_feature().elements;
+        let element = match background_index {
+            Some(index) if is_background_step => &mut elements[index],
+            _ => elements
+                .last_mut()
+                .expect("step visited outside of a scenario"),
+        };
+        element.steps.push(json_step);
+    }
+
+    fn visit_finish(&mut self, _duration: Duration) {
+        serde_json::to_writer_pretty(&mut self.writer, &self.features)
+            .expect("failed to write JSON report");
+        writeln!(&mut self.writer).expect("failed to write JSON report");
+        self.writer.flush().expect("failed to write JSON report");
+    }
+}
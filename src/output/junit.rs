@@ -0,0 +1,319 @@
+use std::io::{self, Write};
+use std::path::Path;
+use std::time::Duration;
+
+use gherkin;
+
+use super::{escape, relpath, ReportOutput};
+use crate::LogRecord;
+use crate::OutputVisitor;
+use crate::TestResult;
+
+/// Writes results as JUnit XML, with one testsuite per feature and one
+/// testcase per scenario or row of examples.
+///
+/// Output is only captured from failing steps, so only failed testcases
+/// have a `<system-out>` and `<system-err>`, apart from any captured logs.
+///
+/// Each testsuite starts with its counts of tests, failures and skips, so the
+/// XML is held back until they are all known.
+pub struct JUnitOutput {
+    writer: Box<dyn Write>,
+    suites: Vec<TestSuite>,
+}
+
+struct TestSuite {
+    name: String,
+    file: String,
+    cases: Vec<TestCase>,
+}
+
+struct TestCase {
+    name: String,
+    line: usize,
+    time: Duration,
+    outcome: Outcome,
+    stdout: Vec<u8>,
+    stderr: Vec<u8>,
+    // The records logged while the scenario ran, written after its stdout.
+    logs: Vec<LogRecord>,
+}
+
+enum Outcome {
+    Pass,
+    Skipped(String),
+    Failure { message: String, details: String },
+    Error(String),
+}
+
+impl ReportOutput for JUnitOutput {
+    fn new(writer: Box<dyn Write>) -> JUnitOutput {
+        JUnitOutput {
+            writer,
+            suites: vec![],
+        }
+    }
+}
+
+impl JUnitOutput {
+    fn current_case(&mut self) -> &mut TestCase {
+        self.suites
+            .last_mut()
+            .and_then(|suite| suite.cases.last_mut())
+            .expect("step visited outside of a scenario")
+    }
+
+    fn write_report(&mut self) -> io::Result<()> {
+        let JUnitOutput {
+            writer: w, suites, ..
+        } = self;
+        let cases = || suites.iter().flat_map(|suite| suite.cases.iter());
+
+        writeln!(w, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+        writeln!(
+            w,
+            r#"<testsuites name="cucumber" tests="{}" failures="{}" errors="{}" skipped="{}" time="{:.3}">"#,
+            cases().count(),
+            cases().filter(|c| c.outcome.is_failure()).count(),
+            cases().filter(|c| c.outcome.is_error()).count(),
+            cases().filter(|c| c.outcome.is_skipped()).count(),
+            cases().map(|c| c.time).sum::<Duration>().as_secs_f64(),
+        )?;
+
+        for suite in suites.iter() {
+            writeln!(
+                w,
+                r#"  <testsuite name="{}" tests="{}" failures="{}" errors="{}" skipped="{}" time="{:.3}" file="{}">"#,
+                escape(&suite.name),
+                suite.cases.len(),
+                suite
+                    .cases
+                    .iter()
+                    .filter(|c| c.outcome.is_failure())
+                    .count(),
+                suite.cases.iter().filter(|c| c.outcome.is_error()).count(),
+                suite
+                    .cases
+                    .iter()
+                    .filter(|c| c.outcome.is_skipped())
+                    .count(),
+                suite
+                    .cases
+                    .iter()
+                    .map(|c| c.time)
+                    .sum::<Duration>()
+                    .as_secs_f64(),
+                escape(&suite.file),
+            )?;
+
+            for case in &suite.cases {
+                write!(
+                    w,
+                    r#"    <testcase name="{}" classname="{}" time="{:.3}" file="{}" line="{}">"#,
+                    escape(&case.name),
+                    escape(&suite.name),
+                    case.time.as_secs_f64(),
+                    escape(&suite.file),
+                    case.line,
+                )?;
+
+                match &case.outcome {
+                    Outcome::Pass => {}
+                    Outcome::Skipped(message) => {
+                        write!(w, r#"<skipped message="{}"/>"#, escape(message))?;
+                    }
+                    Outcome::Failure { message, details } => {
+                        write!(
+                            w,
+                            r#"<failure message="{}" type="panic">{}</failure>"#,
+                            escape(message),
+                            escape(details)
+                        )?;
+                    }
+                    Outcome::Error(message) => {
+                        write!(w, r#"<error message="{}" type="parse"/>"#, escape(message))?;
+                    }
+                }
+
+                if !case.stdout.is_empty() || !case.logs.is_empty() {
+                    let mut out = String::from_utf8_lossy(&case.stdout).into_owned();
+                    for record in &case.logs {
+                        out.push_str(&format!("{}\n", record));
+                    }
+                    write!(w, "<system-out>{}</system-out>", escape(&out))?;
+                }
+
+                if !case.stderr.is_empty() {
+                    write!(
+                        w,
+                        "<system-err>{}</system-err>",
+                        escape(&String::from_utf8_lossy(&case.stderr))
+                    )?;
+                }
+
+                writeln!(w, "</testcase>")?;
+            }
+
+            writeln!(w, "  </testsuite>")?;
+        }
+
+        writeln!(w, "</testsuites>")?;
+        w.flush()
+    }
+}
+
+impl Outcome {
+    fn is_failure(&self) -> bool {
+        matches!(self, Outcome::Failure { .. })
+    }
+
+    fn is_error(&self) -> bool {
+        matches!(self, Outcome::Error(_))
+    }
+
+    fn is_skipped(&self) -> bool {
+        matches!(self, Outcome::Skipped(_))
+    }
+}
+
+impl OutputVisitor for JUnitOutput {
+    fn visit_start(&mut self) {}
+
+    fn visit_feature(&mut self, feature: &gherkin::Feature, path: &Path) {
+        self.suites.push(TestSuite {
+            name: feature.name.clone(),
+            file: relpath(path).to_string_lossy().to_string(),
+            cases: vec![],
+        });
+    }
+
+    fn visit_feature_end(&mut self, _feature: &gherkin::Feature, _duration: Duration) {}
+
+    fn visit_feature_error<'a>(&mut self, path: &Path, error: &gherkin::Error<'a>) {
+        let file = relpath(path).to_string_lossy().to_string();
+        let position = gherkin::error_position(error);
+
+        self.suites.push(TestSuite {
+            name: file.clone(),
+            file,
+            cases: vec![TestCase {
+                name: "Parsing feature".to_string(),
+                line: position.0,
+                time: Duration::default(),
+                outcome: Outcome::Error(error.to_string()),
+                stdout: vec![],
+                stderr: vec![],
+                logs: vec![],
+            }],
+        });
+    }
+
+    fn visit_rule(&mut self, _rule: &gherkin::Rule) {}
+
+    fn visit_rule_end(&mut self, _rule: &gherkin::Rule) {}
+
+    fn visit_scenario(&mut self, rule: Option<&gherkin::Rule>, scenario: &gherkin::Scenario) {
+        let name = match rule {
+            Some(rule) => format!("{} / {}", rule.name, scenario.name),
+            None => scenario.name.clone(),
+        };
+
+        self.suites
+            .last_mut()
+            .expect("scenario visited outside of a feature")
+            .cases
+            .push(TestCase {
+                name,
+                line: scenario.position.0,
+                time: Duration::default(),
+                outcome: Outcome::Pass,
+                stdout: vec![],
+                stderr: vec![],
+                logs: vec![],
+            });
+    }
+
+    fn visit_scenario_end(
+        &mut self,
+        _rule: Option<&gherkin::Rule>,
+        _scenario: &gherkin::Scenario,
+        duration: Duration,
+    ) {
+        self.current_case().time = duration;
+    }
+
+    fn visit_scenario_skipped(
+        &mut self,
+        _rule: Option<&gherkin::Rule>,
+        _scenario: &gherkin::Scenario,
+    ) {
+    }
+
+    fn visit_step(
+        &mut self,
+        _rule: Option<&gherkin::Rule>,
+        _scenario: &gherkin::Scenario,
+        _step: &gherkin::Step,
+    ) {
+    }
+
+    fn visit_logs(
+        &mut self,
+        _rule: Option<&gherkin::Rule>,
+        _scenario: &gherkin::Scenario,
+        _step: Option<&gherkin::Step>,
+        records: &[LogRecord],
+    ) {
+        self.current_case().logs.extend_from_slice(records);
+    }
+
+    fn visit_step_result(
+        &mut self,
+        _rule: Option<&gherkin::Rule>,
+        _scenario: &gherkin::Scenario,
+        step: &gherkin::Step,
+        result: &TestResult,
+        _duration: Duration,
+    ) {
+        let case = self.current_case();
+
+        // Only the first step that did not pass decides the outcome.
+        if !matches!(case.outcome, Outcome::Pass) {
+            return;
+        }
+
+        match result {
+            TestResult::Pass => {}
+            TestResult::Fail(panic_info, stdout, stderr) => {
+                case.outcome = Outcome::Failure {
+                    message: panic_info.payload.clone(),
+                    details: format!(
+                        "Step failed: {}\n{}\n{}",
+                        step.to_string(),
+                        panic_info.payload,
+                        panic_info.location
+                    ),
+                };
+                case.stdout = stdout.clone();
+                case.stderr = stderr.clone();
+            }
+            TestResult::MutexPoisoned => {
+                case.outcome = Outcome::Failure {
+                    message: "Skipped due to previous error (poisoned)".to_string(),
+                    details: format!("Step failed: {}", step.to_string()),
+                };
+            }
+            TestResult::Skipped => {
+                case.outcome = Outcome::Skipped(format!("Step skipped: {}", step.to_string()));
+            }
+            TestResult::Unimplemented => {
+                case.outcome =
+                    Outcome::Skipped(format!("Step not yet implemented: {}", step.to_string()));
+            }
+        }
+    }
+
+    fn visit_finish(&mut self, _duration: Duration) {
+        self.write_report().expect("failed to write JUnit report");
+    }
+}
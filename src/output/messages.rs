@@ -0,0 +1,659 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use gherkin;
+use serde_json::{json, Value};
+
+use super::{relpath, ReportOutput};
+use crate::table;
+use crate::DocstringExt;
+use crate::LogRecord;
+use crate::OutputVisitor;
+use crate::TestResult;
+use crate::{StepDefinition, StepPattern};
+
+const PROTOCOL_VERSION: &str = "22.0.0";
+
+/// Writes results as a stream of Cucumber Messages, one JSON object per
+/// line, which the official HTML formatter and other Cucumber tooling can
+/// read.
+///
+/// The IDs of the nodes of each feature are built from their locations, and
+/// those of step definitions from their patterns, so that they are the same
+/// from run to run.
+pub struct MessagesOutput {
+    writer: Box<dyn Write>,
+    definitions: Vec<StepDefinition>,
+    uri: String,
+    background: Vec<gherkin::Step>,
+    pickles: HashMap<usize, PickleSource>,
+    test_case: Option<TestCase>,
+    is_success: bool,
+}
+
+/// What a pickle is made from in the gherkin document, keyed by the line of
+/// its scenario or row of examples.
+struct PickleSource {
+    ast_node_ids: Vec<String>,
+    tags: Vec<Value>,
+}
+
+struct TestCase {
+    started_id: String,
+    step_ids: Vec<String>,
+    step_index: usize,
+}
+
+impl ReportOutput for MessagesOutput {
+    fn new(writer: Box<dyn Write>) -> MessagesOutput {
+        MessagesOutput {
+            writer,
+            definitions: vec![],
+            uri: String::new(),
+            background: vec![],
+            pickles: HashMap::new(),
+            test_case: None,
+            is_success: true,
+        }
+    }
+}
+
+impl MessagesOutput {
+    fn write(&mut self, envelope: Value) {
+        serde_json::to_writer(&mut self.writer, &envelope).expect("failed to write message");
+        writeln!(&mut self.writer).expect("failed to write message");
+    }
+
+    fn node_id(&self, position: (usize, usize)) -> String {
+        format!("{}:{}:{}", self.uri, position.0, position.1)
+    }
+
+    fn tags(&self, tags: &Option<Vec<String>>, owner: (usize, usize)) -> Vec<Value> {
+        tags.iter()
+            .flatten()
+            .map(|name| {
+                json!({
+                    "location": location(owner),
+                    "name": name,
+                    "id": format!("{}{}", self.node_id(owner), name),
+                })
+            })
+            .collect()
+    }
+
+    fn pickle_tags(&self, tags: &Option<Vec<String>>, owner: (usize, usize)) -> Vec<Value> {
+        self.tags(tags, owner)
+            .into_iter()
+            .map(|tag| json!({ "name": tag["name"], "astNodeId": tag["id"] }))
+            .collect()
+    }
+
+    fn step_node(&self, step: &gherkin::Step) -> Value {
+        let mut node = json!({
+            "id": self.node_id(step.position),
+            "location": location(step.position),
+            "keyword": format!("{} ", step.raw_type),
+            "keywordType": keyword_type(step),
+            "text": step.value,
+        });
+
+        if let Some(docstring) = &step.docstring {
+            node["docString"] = json!({
+                "location": location((step.position.0 + 1, step.position.1 + 2)),
+                "content": docstring,
+                "delimiter": "\"\"\"",
+            });
+            if let Some(content_type) = step.docstring_content_type() {
+                node["docString"]["mediaType"] = json!(content_type);
+            }
+        }
+
+        if let Some(data_table) = &step.table {
+            node["dataTable"] = json!({
+                "location": location((table::header_line(data_table), step.position.1 + 2)),
+                "rows": self.table_rows(data_table, step.position.1 + 2),
+            });
+        }
+
+        node
+    }
+
+    fn table_row(&self, cells: &[String], line: usize, column: usize) -> Value {
+        json!({
+            "id": self.node_id((line, column)),
+            "location": location((line, column)),
+            "cells": cells
+                .iter()
+                .map(|value| json!({ "location": location((line, column)), "value": value }))
+                .collect::<Vec<_>>(),
+        })
+    }
+
+    fn table_rows(&self, data_table: &gherkin::Table, column: usize) -> Vec<Value> {
+        std::iter::once(self.table_row(&data_table.header, table::header_line(data_table), column))
+            .chain(
+                data_table
+                    .rows
+                    .iter()
+                    .enumerate()
+                    .map(|(n, row)| self.table_row(row, table::row_line(data_table, n), column)),
+            )
+            .collect()
+    }
+
+    fn scenario_node(&self, scenario: &gherkin::Scenario) -> Value {
+        let examples = scenario.examples.iter().map(|examples| {
+            let column = examples.position.1;
+            let rows = self.table_rows(&examples.table, column);
+
+            json!({
+                "id": self.node_id(examples.position),
+                "location": location(examples.position),
+                "tags": self.tags(&examples.tags, examples.position),
+                "keyword": "Examples",
+                "name": "",
+                "description": "",
+                "tableHeader": rows[0],
+                "tableBody": rows[1..],
+            })
+        });
+
+        json!({
+            "scenario": {
+                "id": self.node_id(scenario.position),
+                "location": location(scenario.position),
+                "tags": self.tags(&scenario.tags, scenario.position),
+                "keyword": if scenario.examples.is_some() { "Scenario Outline" } else { "Scenario" },
+                "name": scenario.name,
+                "description": "",
+                "steps": scenario.steps.iter().map(|step| self.step_node(step)).collect::<Vec<_>>(),
+                "examples": examples.collect::<Vec<_>>(),
+            }
+        })
+    }
+
+    /// Records what the pickles of `scenarios` will be made from, as the
+    /// scenarios visited are those already expanded from outlines.
+    fn add_pickle_sources(&mut self, scenarios: &[gherkin::Scenario], inherited_tags: &[Value]) {
+        for scenario in scenarios {
+            let scenario_id = self.node_id(scenario.position);
+            let mut tags = inherited_tags.to_vec();
+            tags.extend(self.pickle_tags(&scenario.tags, scenario.position));
+
+            let examples = match &scenario.examples {
+                Some(examples) => examples,
+                None => {
+                    self.pickles.insert(
+                        scenario.position.0,
+                        PickleSource {
+                            ast_node_ids: vec![scenario_id],
+                            tags,
+                        },
+                    );
+                    continue;
+                }
+            };
+
+            tags.extend(self.pickle_tags(&examples.tags, examples.position));
+            for n in 0..examples.table.rows.len() {
+                let line = table::row_line(&examples.table, n);
+                self.pickles.insert(
+                    line,
+                    PickleSource {
+                        ast_node_ids: vec![
+                            scenario_id.clone(),
+                            self.node_id((line, examples.position.1)),
+                        ],
+                        tags: tags.clone(),
+                    },
+                );
+            }
+        }
+    }
+
+    fn test_step(&self, step: &gherkin::Step, id: String, pickle_step_id: String) -> Value {
+        let definition = match StepDefinition::find(&self.definitions, step) {
+            Some(definition) => definition,
+            None => {
+                return json!({
+                    "id": id,
+                    "pickleStepId": pickle_step_id,
+                    "stepDefinitionIds": [],
+                })
+            }
+        };
+
+        let arguments = match &definition.pattern {
+            StepPattern::Text(_) => vec![],
+            StepPattern::Regex(regex) => regex
+                .captures(&step.value)
+                .iter()
+                .flat_map(|captures| captures.iter().skip(1))
+                .map(|capture| match capture {
+                    Some(capture) => json!({
+                        "group": {
+                            "start": capture.start(),
+                            "value": capture.as_str(),
+                            "children": [],
+                        }
+                    }),
+                    None => json!({ "group": { "children": [] } }),
+                })
+                .collect(),
+        };
+
+        json!({
+            "id": id,
+            "pickleStepId": pickle_step_id,
+            "stepDefinitionIds": [definition.id()],
+            "stepMatchArgumentsLists": [{ "stepMatchArguments": arguments }],
+        })
+    }
+}
+
+fn location(position: (usize, usize)) -> Value {
+    json!({ "line": position.0, "column": position.1 })
+}
+
+fn timestamp() -> Value {
+    let since_epoch = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    duration(since_epoch)
+}
+
+fn duration(duration: Duration) -> Value {
+    json!({ "seconds": duration.as_secs(), "nanos": duration.subsec_nanos() })
+}
+
+fn keyword_type(step: &gherkin::Step) -> &'static str {
+    match (step.raw_type.as_str(), step.ty) {
+        ("And", _) | ("But", _) => "Conjunction",
+        (_, gherkin::StepType::Given) => "Context",
+        (_, gherkin::StepType::When) => "Action",
+        (_, gherkin::StepType::Then) => "Outcome",
+    }
+}
+
+fn pickle_step_type(step: &gherkin::Step) -> &'static str {
+    match step.ty {
+        gherkin::StepType::Given => "Context",
+        gherkin::StepType::When => "Action",
+        gherkin::StepType::Then => "Outcome",
+    }
+}
+
+fn pickle_step_argument(step: &gherkin::Step) -> Option<Value> {
+    if let Some(docstring) = &step.docstring {
+        let mut argument = json!({ "docString": { "content": docstring } });
+        if let Some(content_type) = step.docstring_content_type() {
+            argument["docString"]["mediaType"] = json!(content_type);
+        }
+        return Some(argument);
+    }
+
+    step.table.as_ref().map(|data_table| {
+        let rows = std::iter::once(&data_table.header)
+            .chain(data_table.rows.iter())
+            .map(|row| {
+                json!({
+                    "cells": row
+                        .iter()
+                        .map(|value| json!({ "value": value }))
+                        .collect::<Vec<_>>(),
+                })
+            })
+            .collect::<Vec<_>>();
+
+        json!({ "dataTable": { "rows": rows } })
+    })
+}
+
+impl OutputVisitor for MessagesOutput {
+    fn visit_step_definitions(&mut self, definitions: &[StepDefinition]) {
+        self.definitions = definitions.to_vec();
+    }
+
+    fn visit_start(&mut self) {
+        self.write(json!({
+            "meta": {
+                "protocolVersion": PROTOCOL_VERSION,
+                "implementation": {
+                    "name": env!("CARGO_PKG_NAME"),
+                    "version": env!("CARGO_PKG_VERSION"),
+                },
+                "runtime": { "name": "rust" },
+                "os": { "name": std::env::consts::OS },
+                "cpu": { "name": std::env::consts::ARCH },
+            }
+        }));
+
+        for definition in self.definitions.clone() {
+            // Steps matching exact text are reported as the equivalent regex.
+            let source = match &definition.pattern {
+                StepPattern::Text(text) => format!("^{}$", regex::escape(text)),
+                StepPattern::Regex(regex) => regex.as_str().to_string(),
+            };
+
+            self.write(json!({
+                "stepDefinition": {
+                    "id": definition.id(),
+                    "pattern": { "source": source, "type": "REGULAR_EXPRESSION" },
+                }
+            }));
+        }
+
+        self.write(json!({ "testRunStarted": { "timestamp": timestamp() } }));
+    }
+
+    fn visit_feature(&mut self, feature: &gherkin::Feature, path: &Path) {
+        self.uri = relpath(path).to_string_lossy().to_string();
+        self.background = feature
+            .background
+            .iter()
+            .flat_map(|background| background.steps.iter().cloned())
+            .collect();
+
+        let data = fs::read_to_string(path).unwrap_or_default();
+        self.write(json!({
+            "source": {
+                "uri": self.uri,
+                "data": data,
+                "mediaType": "text/x.cucumber.gherkin+plain",
+            }
+        }));
+
+        let mut children = vec![];
+        if let Some(background) = &feature.background {
+            children.push(json!({
+                "background": {
+                    "id": self.node_id(background.position),
+                    "location": location(background.position),
+                    "keyword": "Background",
+                    "name": "",
+                    "description": "",
+                    "steps": background.steps.iter().map(|step| self.step_node(step)).collect::<Vec<_>>(),
+                }
+            }));
+        }
+        children.extend(feature.scenarios.iter().map(|s| self.scenario_node(s)));
+        children.extend(feature.rules.iter().map(|rule| {
+            json!({
+                "rule": {
+                    "id": self.node_id(rule.position),
+                    "location": location(rule.position),
+                    "tags": self.tags(&rule.tags, rule.position),
+                    "keyword": "Rule",
+                    "name": rule.name,
+                    "description": "",
+                    "children": rule.scenarios.iter().map(|s| self.scenario_node(s)).collect::<Vec<_>>(),
+                }
+            })
+        }));
+
+        self.write(json!({
+            "gherkinDocument": {
+                "uri": self.uri,
+                "feature": {
+                    "location": location(feature.position),
+                    "tags": self.tags(&feature.tags, feature.position),
+                    "language": "en",
+                    "keyword": "Feature",
+                    "name": feature.name,
+                    "description": feature.description.clone().unwrap_or_default(),
+                    "children": children,
+                },
+                "comments": [],
+            }
+        }));
+
+        self.pickles.clear();
+        let feature_tags = self.pickle_tags(&feature.tags, feature.position);
+        self.add_pickle_sources(&feature.scenarios, &feature_tags);
+        for rule in &feature.rules {
+            let mut rule_tags = feature_tags.clone();
+            rule_tags.extend(self.pickle_tags(&rule.tags, rule.position));
+            self.add_pickle_sources(&rule.scenarios, &rule_tags);
+        }
+    }
+
+    fn visit_feature_end(&mut self, _feature: &gherkin::Feature, _duration: Duration) {}
+
+    fn visit_feature_error<'a>(&mut self, path: &Path, error: &gherkin::Error<'a>) {
+        self.is_success = false;
+        let uri = relpath(path).to_string_lossy().to_string();
+        let position = gherkin::error_position(error);
+        let data = fs::read_to_string(path).unwrap_or_default();
+
+        self.write(json!({
+            "source": {
+                "uri": uri,
+                "data": data,
+                "mediaType": "text/x.cucumber.gherkin+plain",
+            }
+        }));
+        self.write(json!({
+            "parseError": {
+                "source": { "uri": uri, "location": location(position) },
+                "message": error.to_string(),
+            }
+        }));
+    }
+
+    fn visit_rule(&mut self, _rule: &gherkin::Rule) {}
+
+    fn visit_rule_end(&mut self, _rule: &gherkin::Rule) {}
+
+    fn visit_scenario(&mut self, _rule: Option<&gherkin::Rule>, scenario: &gherkin::Scenario) {
+        let scenario_id = self.node_id(scenario.position);
+        let (ast_node_ids, tags) = match self.pickles.get(&scenario.position.0) {
+            Some(source) => (source.ast_node_ids.clone(), source.tags.clone()),
+            None => (vec![scenario_id.clone()], vec![]),
+        };
+
+        let pickle_id = format!("{}#pickle", scenario_id);
+        let test_case_id = format!("{}#test-case", scenario_id);
+        let steps = self
+            .background
+            .iter()
+            .chain(scenario.steps.iter())
+            .cloned()
+            .collect::<Vec<_>>();
+
+        let pickle_steps = steps
+            .iter()
+            .enumerate()
+            .map(|(n, step)| {
+                let mut step_ast_node_ids = vec![self.node_id(step.position)];
+                step_ast_node_ids.extend(ast_node_ids.iter().skip(1).cloned());
+
+                let mut pickle_step = json!({
+                    "id": format!("{}/{}", pickle_id, n),
+                    "text": step.value,
+                    "type": pickle_step_type(step),
+                    "astNodeIds": step_ast_node_ids,
+                });
+                if let Some(argument) = pickle_step_argument(step) {
+                    pickle_step["argument"] = argument;
+                }
+                pickle_step
+            })
+            .collect::<Vec<_>>();
+
+        self.write(json!({
+            "pickle": {
+                "id": pickle_id,
+                "uri": self.uri,
+                "name": scenario.name,
+                "language": "en",
+                "steps": pickle_steps,
+                "tags": tags,
+                "astNodeIds": ast_node_ids,
+            }
+        }));
+
+        let step_ids = (0..steps.len())
+            .map(|n| format!("{}/{}", test_case_id, n))
+            .collect::<Vec<_>>();
+        let test_steps = steps
+            .iter()
+            .zip(step_ids.iter())
+            .enumerate()
+            .map(|(n, (step, id))| self.test_step(step, id.clone(), format!("{}/{}", pickle_id, n)))
+            .collect::<Vec<_>>();
+
+        self.write(json!({
+            "testCase": {
+                "id": test_case_id,
+                "pickleId": pickle_id,
+                "testSteps": test_steps,
+            }
+        }));
+
+        let started_id = format!("{}#attempt-0", test_case_id);
+        self.write(json!({
+            "testCaseStarted": {
+                "id": started_id,
+                "testCaseId": test_case_id,
+                "attempt": 0,
+                "timestamp": timestamp(),
+            }
+        }));
+
+        self.test_case = Some(TestCase {
+            started_id,
+            step_ids,
+            step_index: 0,
+        });
+    }
+
+    fn visit_scenario_end(
+        &mut self,
+        _rule: Option<&gherkin::Rule>,
+        _scenario: &gherkin::Scenario,
+        _duration: Duration,
+    ) {
+        if let Some(test_case) = self.test_case.take() {
+            self.write(json!({
+                "testCaseFinished": {
+                    "testCaseStartedId": test_case.started_id,
+                    "timestamp": timestamp(),
+                    "willBeRetried": false,
+                }
+            }));
+        }
+    }
+
+    fn visit_scenario_skipped(
+        &mut self,
+        _rule: Option<&gherkin::Rule>,
+        _scenario: &gherkin::Scenario,
+    ) {
+    }
+
+    fn visit_step(
+        &mut self,
+        _rule: Option<&gherkin::Rule>,
+        _scenario: &gherkin::Scenario,
+        _step: &gherkin::Step,
+    ) {
+        let test_case = self
+            .test_case
+            .as_ref()
+            .expect("step visited outside of a scenario");
+        let envelope = json!({
+            "testStepStarted": {
+                "testCaseStartedId": test_case.started_id,
+                "testStepId": test_case.step_ids[test_case.step_index],
+                "timestamp": timestamp(),
+            }
+        });
+
+        self.write(envelope);
+    }
+
+    fn visit_logs(
+        &mut self,
+        _rule: Option<&gherkin::Rule>,
+        _scenario: &gherkin::Scenario,
+        step: Option<&gherkin::Step>,
+        records: &[LogRecord],
+    ) {
+        let test_case = self
+            .test_case
+            .as_ref()
+            .expect("logs visited outside of a scenario");
+        let mut attachment = json!({
+            "contentEncoding": "IDENTITY",
+            "mediaType": "text/x.cucumber.log+plain",
+            "testCaseStartedId": test_case.started_id,
+        });
+        if step.is_some() {
+            attachment["testStepId"] = json!(test_case.step_ids[test_case.step_index]);
+        }
+
+        for record in records {
+            attachment["body"] = json!(record.to_string());
+            self.write(json!({ "attachment": attachment.clone() }));
+        }
+    }
+
+    fn visit_step_result(
+        &mut self,
+        _rule: Option<&gherkin::Rule>,
+        _scenario: &gherkin::Scenario,
+        _step: &gherkin::Step,
+        result: &TestResult,
+        elapsed: Duration,
+    ) {
+        let mut step_result = match result {
+            TestResult::Pass => json!({ "status": "PASSED" }),
+            TestResult::Fail(panic_info, _, _) => json!({
+                "status": "FAILED",
+                "message": format!("{}\n{}", panic_info.payload, panic_info.location),
+                "exception": { "type": "panic", "message": panic_info.payload },
+            }),
+            TestResult::MutexPoisoned => json!({
+                "status": "FAILED",
+                "message": "Skipped due to previous error (poisoned)",
+            }),
+            TestResult::Skipped => json!({ "status": "SKIPPED" }),
+            TestResult::Unimplemented => json!({ "status": "UNDEFINED" }),
+        };
+        step_result["duration"] = duration(elapsed);
+
+        if matches!(
+            result,
+            TestResult::Fail(_, _, _) | TestResult::MutexPoisoned
+        ) {
+            self.is_success = false;
+        }
+
+        let test_case = self
+            .test_case
+            .as_mut()
+            .expect("step visited outside of a scenario");
+        let envelope = json!({
+            "testStepFinished": {
+                "testCaseStartedId": test_case.started_id,
+                "testStepId": test_case.step_ids[test_case.step_index],
+                "testStepResult": step_result,
+                "timestamp": timestamp(),
+            }
+        });
+        test_case.step_index += 1;
+
+        self.write(envelope);
+    }
+
+    fn visit_finish(&mut self, _duration: Duration) {
+        let is_success = self.is_success;
+        self.write(json!({
+            "testRunFinished": { "success": is_success, "timestamp": timestamp() }
+        }));
+        self.writer.flush().expect("failed to write message");
+    }
+}
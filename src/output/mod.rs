@@ -1,20 +1,60 @@
 pub mod default;
+pub mod html;
+pub mod json;
+pub mod junit;
+pub mod messages;
+pub mod multi;
+pub mod rerun;
+pub mod slowest;
 
-use std::path::Path;
+use std::collections::HashMap;
+use std::env;
+use std::fs::File;
+use std::io::{self, BufWriter, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
 
-use gherkin;
+use pathdiff::diff_paths;
 
-use crate::TestResult;
+use crate::{HookType, LogRecord, StepDefinition, TestResult};
+use default::DefaultOutput;
+use html::HtmlOutput;
+use json::JsonOutput;
+use junit::JUnitOutput;
+use messages::MessagesOutput;
+use rerun::RerunOutput;
 
-pub trait OutputVisitor: Default {
+/// Receives the events of a run, to report them.
+///
+/// Outputs are used as `Box<dyn OutputVisitor>`, so that they can be chosen
+/// when the tests are run. See `register_output` for making an output
+/// available to `--format`.
+pub trait OutputVisitor {
+    /// Called with the registered step definitions before the run starts.
+    fn visit_step_definitions(&mut self, _definitions: &[StepDefinition]) {}
     fn visit_start(&mut self);
     fn visit_feature(&mut self, feature: &gherkin::Feature, path: &Path);
-    fn visit_feature_end(&mut self, feature: &gherkin::Feature);
+    fn visit_feature_end(&mut self, feature: &gherkin::Feature, duration: Duration);
     fn visit_feature_error<'a>(&mut self, path: &Path, error: &gherkin::Error<'a>);
     fn visit_rule(&mut self, rule: &gherkin::Rule);
     fn visit_rule_end(&mut self, rule: &gherkin::Rule);
     fn visit_scenario(&mut self, rule: Option<&gherkin::Rule>, scenario: &gherkin::Scenario);
-    fn visit_scenario_end(&mut self, rule: Option<&gherkin::Rule>, scenario: &gherkin::Scenario);
+    fn visit_scenario_end(
+        &mut self,
+        rule: Option<&gherkin::Rule>,
+        scenario: &gherkin::Scenario,
+        duration: Duration,
+    );
+    /// Called after the before or after hooks of a scenario have run.
+    fn visit_hook(
+        &mut self,
+        _rule: Option<&gherkin::Rule>,
+        _scenario: &gherkin::Scenario,
+        _hook: HookType,
+        _duration: Duration,
+    ) {
+    }
     fn visit_scenario_skipped(
         &mut self,
         rule: Option<&gherkin::Rule>,
@@ -26,12 +66,294 @@ pub trait OutputVisitor: Default {
         scenario: &gherkin::Scenario,
         step: &gherkin::Step,
     );
+    /// Called with the `log` and `tracing` records of a step, before its
+    /// result, or with those of the scenario outside its steps, before its
+    /// end. Records are only captured with the `capture-logs` feature.
+    fn visit_logs(
+        &mut self,
+        _rule: Option<&gherkin::Rule>,
+        _scenario: &gherkin::Scenario,
+        _step: Option<&gherkin::Step>,
+        _records: &[LogRecord],
+    ) {
+    }
     fn visit_step_result(
         &mut self,
         rule: Option<&gherkin::Rule>,
         scenario: &gherkin::Scenario,
         step: &gherkin::Step,
         result: &TestResult,
+        duration: Duration,
     );
-    fn visit_finish(&mut self);
+    /// Called once the run has finished, with how long it took.
+    fn visit_finish(&mut self, duration: Duration);
+}
+
+/// An output writing a report, to a file or to stdout.
+pub trait ReportOutput: OutputVisitor + Sized {
+    /// Creates the output writing its report to `writer`.
+    fn new(writer: Box<dyn Write>) -> Self;
+
+    /// Creates the output writing its report to the file at `path`.
+    fn create(path: &Path) -> io::Result<Self> {
+        let file = File::create(path)?;
+        Ok(Self::new(Box::new(BufWriter::new(file))))
+    }
+
+    /// Creates the output writing its report to stdout.
+    fn stdout() -> Self {
+        Self::new(Box::new(io::stdout()))
+    }
+}
+
+/// The records logged by a step, held from `visit_logs` until the step's
+/// result is visited.
+#[derive(Debug, Default)]
+pub(crate) struct PendingLogs(Vec<LogRecord>);
+
+impl PendingLogs {
+    /// Holds the records logged by the step whose result is visited next.
+    pub(crate) fn hold(&mut self, records: &[LogRecord]) {
+        self.0 = records.to_vec();
+    }
+
+    /// The records of the step whose result is being visited.
+    pub(crate) fn take(&mut self) -> Vec<LogRecord> {
+        std::mem::take(&mut self.0)
+    }
+}
+
+/// The path of `target` relative to the current directory.
+pub(crate) fn relpath(target: &Path) -> PathBuf {
+    let target = target.canonicalize().expect("invalid target path");
+    diff_paths(
+        &target,
+        &env::current_dir().expect("invalid current directory"),
+    )
+    .expect("invalid target path")
+}
+
+/// Escapes `s` for use in XML or HTML text and attribute values, leaving out
+/// the control characters XML 1.0 does not allow.
+pub(crate) fn escape(s: &str) -> String {
+    s.chars()
+        .filter(|c| !c.is_control() || matches!(c, '\t' | '\n' | '\r'))
+        .filter(|c| !matches!(c, '\u{fffe}' | '\u{ffff}'))
+        .fold(String::with_capacity(s.len()), |mut out, c| {
+            match c {
+                '&' => out.push_str("&amp;"),
+                '<' => out.push_str("&lt;"),
+                '>' => out.push_str("&gt;"),
+                '"' => out.push_str("&quot;"),
+                '\'' => out.push_str("&apos;"),
+                c => out.push(c),
+            }
+            out
+        })
+}
+
+/// How much an output should report.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum Verbosity {
+    /// Only what went wrong, and the summary.
+    Quiet,
+    #[default]
+    Normal,
+    /// Everything, including details normally left out.
+    Verbose,
+}
+
+/// Whether an output writing to a terminal should use color.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ColorMode {
+    /// Color when writing to a terminal, unless the `NO_COLOR` environment
+    /// variable is set. `CLICOLOR_FORCE` forces color even when not.
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorMode {
+    /// Whether to color output written to stdout.
+    pub fn use_color(self) -> bool {
+        let is_set = |name| env::var_os(name).is_some_and(|v| !v.is_empty() && v != "0");
+
+        match self {
+            ColorMode::Always => true,
+            ColorMode::Never => false,
+            ColorMode::Auto if env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty()) => false,
+            ColorMode::Auto if is_set("CLICOLOR_FORCE") => true,
+            ColorMode::Auto => io::stdout().is_terminal(),
+        }
+    }
+}
+
+/// The configuration an output is created with.
+#[derive(Debug, Clone, Default)]
+pub struct OutputConfig {
+    /// The file to write to, or `None` for stdout.
+    pub path: Option<PathBuf>,
+    pub verbosity: Verbosity,
+    pub color: ColorMode,
+    /// The width to wrap terminal output at, with `Some(0)` for no wrapping.
+    /// When `None`, output to a terminal is wrapped at its width, and other
+    /// output is not wrapped.
+    pub width: Option<usize>,
+}
+
+/// Creates an output from its configuration.
+pub type OutputFactory = fn(&OutputConfig) -> io::Result<Box<dyn OutputVisitor>>;
+
+fn factories() -> &'static Mutex<HashMap<String, OutputFactory>> {
+    static FACTORIES: OnceLock<Mutex<HashMap<String, OutputFactory>>> = OnceLock::new();
+
+    FACTORIES.get_or_init(|| {
+        let mut factories = HashMap::new();
+        factories.insert("pretty".to_string(), pretty as OutputFactory);
+        factories.insert("json".to_string(), to_file_or_stdout::<JsonOutput>);
+        factories.insert("html".to_string(), to_file_or_stdout::<HtmlOutput>);
+        factories.insert("junit".to_string(), to_file_or_stdout::<JUnitOutput>);
+        factories.insert("message".to_string(), to_file_or_stdout::<MessagesOutput>);
+        factories.insert("rerun".to_string(), rerun as OutputFactory);
+        Mutex::new(factories)
+    })
+}
+
+fn pretty(config: &OutputConfig) -> io::Result<Box<dyn OutputVisitor>> {
+    match &config.path {
+        Some(_) => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "the pretty format only writes to stdout",
+        )),
+        None => Ok(Box::new(DefaultOutput::new(config))),
+    }
+}
+
+fn to_file_or_stdout<O: ReportOutput + 'static>(
+    config: &OutputConfig,
+) -> io::Result<Box<dyn OutputVisitor>> {
+    let output = match &config.path {
+        Some(path) => O::create(path).map_err(|e| create_error(path, e))?,
+        None => O::stdout(),
+    };
+
+    Ok(Box::new(output))
+}
+
+fn rerun(config: &OutputConfig) -> io::Result<Box<dyn OutputVisitor>> {
+    match &config.path {
+        Some(path) => Ok(Box::new(
+            RerunOutput::create(path).map_err(|e| create_error(path, e))?,
+        )),
+        None => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "the rerun format only writes to a file, given as rerun:<path>",
+        )),
+    }
+}
+
+fn create_error(path: &Path, error: io::Error) -> io::Error {
+    io::Error::new(
+        error.kind(),
+        format!("Failed to create {}: {}", path.display(), error),
+    )
+}
+
+/// Makes an output available to `--format` under `name`, replacing any output
+/// already registered under it.
+///
+/// Outputs must be registered before the outputs for a run are created, which
+/// for the `cucumber!` macro means in its `setup` function.
+pub fn register_output(name: &str, factory: OutputFactory) {
+    factories()
+        .lock()
+        .expect("output registry poisoned")
+        .insert(name.to_string(), factory);
+}
+
+/// Creates the output registered under `name`.
+pub fn create_output(name: &str, config: &OutputConfig) -> io::Result<Box<dyn OutputVisitor>> {
+    let factory = factories()
+        .lock()
+        .expect("output registry poisoned")
+        .get(name)
+        .copied();
+
+    match factory {
+        Some(factory) => factory(config),
+        None => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("unknown output format `{}`", name),
+        )),
+    }
+}
+
+/// The command line that runs the current test binary again with `args`.
+///
+/// Test binaries built by Cargo are given as `cargo test --test <name>`, so
+/// that the command still works after they are rebuilt.
+pub(crate) fn rerun_command(args: &[String]) -> String {
+    let exe = env::args_os().next().map(PathBuf::from);
+    let in_deps = exe
+        .as_ref()
+        .and_then(|exe| exe.parent())
+        .and_then(|dir| dir.file_name())
+        .is_some_and(|dir| dir == "deps");
+
+    let mut command = match &exe {
+        Some(exe) if in_deps => {
+            let stem = exe.file_stem().unwrap_or_default().to_string_lossy();
+            // Cargo names test binaries `<name>-<hash>`.
+            let name = match stem.rsplit_once('-') {
+                Some((name, hash)) if hash.chars().all(|c| c.is_ascii_hexdigit()) => name,
+                _ => &stem,
+            };
+            format!("cargo test --test {} --", shell_quote(name))
+        }
+        Some(exe) => shell_quote(&exe.to_string_lossy()),
+        None => "cargo test --".to_string(),
+    };
+
+    for arg in args {
+        command.push(' ');
+        command.push_str(&shell_quote(arg));
+    }
+    command
+}
+
+/// `s` quoted for a POSIX shell, if it needs to be.
+pub(crate) fn shell_quote(s: &str) -> String {
+    let is_plain = |c: char| c.is_ascii_alphanumeric() || "/._-:@%+=,".contains(c);
+
+    if !s.is_empty() && s.chars().all(is_plain) {
+        s.to_string()
+    } else {
+        format!("'{}'", s.replace('\'', "'\\''"))
+    }
+}
+
+/// `duration` in seconds, to the millisecond.
+pub(crate) fn format_duration(duration: Duration) -> String {
+    format!("{:.3}s", duration.as_secs_f64())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::escape;
+
+    #[test]
+    fn escape_replaces_markup() {
+        assert_eq!(
+            escape(r#"<a href="x">'b' & c</a>"#),
+            "&lt;a href=&quot;x&quot;&gt;&apos;b&apos; &amp; c&lt;/a&gt;"
+        );
+    }
+
+    #[test]
+    fn escape_keeps_only_the_control_characters_xml_allows() {
+        assert_eq!(escape("a\tb\nc\rd"), "a\tb\nc\rd");
+        assert_eq!(escape("a\u{0}b\u{b}c\u{c}d\u{1b}e"), "abcde");
+        assert_eq!(escape("a\u{fffe}b\u{ffff}c"), "abc");
+    }
 }
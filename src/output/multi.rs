@@ -0,0 +1,200 @@
+use std::io::{self, Write};
+use std::path::Path;
+use std::time::Duration;
+
+use gherkin;
+
+use super::slowest::SlowestOutput;
+use super::{create_output, OutputConfig, OutputVisitor};
+use crate::cli::{CliOptions, OutputFormat};
+use crate::isolation::ChildOutput;
+use crate::TestResult;
+use crate::{HookType, LogRecord, StepDefinition};
+
+/// Passes every event on to each of several outputs, in the order they were
+/// added, so that a run can be reported in several formats at once.
+#[derive(Default)]
+pub struct MultiOutput {
+    outputs: Vec<Box<dyn OutputVisitor>>,
+}
+
+impl MultiOutput {
+    pub fn new(outputs: Vec<Box<dyn OutputVisitor>>) -> MultiOutput {
+        MultiOutput { outputs }
+    }
+
+    /// Creates the outputs for the formats given on the command line, each
+    /// with `config` and the path of its format.
+    pub fn from_formats(
+        formats: &[OutputFormat],
+        config: &OutputConfig,
+    ) -> io::Result<MultiOutput> {
+        let outputs = formats
+            .iter()
+            .map(|format| {
+                let config = OutputConfig {
+                    path: format.path.clone(),
+                    ..config.clone()
+                };
+                create_output(&format.name, &config)
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+
+        Ok(MultiOutput::new(outputs))
+    }
+
+    /// Creates the outputs for the options given on the command line.
+    pub fn from_options(options: &CliOptions) -> io::Result<MultiOutput> {
+        if options.is_isolated_child {
+            return Ok(MultiOutput::new(vec![Box::new(ChildOutput)]));
+        }
+
+        let config = OutputConfig {
+            path: None,
+            verbosity: options.verbosity,
+            color: options.color,
+            width: options.width,
+        };
+        let mut output = MultiOutput::from_formats(&options.formats, &config)?;
+
+        if let Some(count) = options.report_slowest {
+            let writer: Box<dyn Write> = if options.stdout_is_report() {
+                Box::new(io::stderr())
+            } else {
+                Box::new(io::stdout())
+            };
+            output.push(SlowestOutput::new(count, writer));
+        }
+
+        Ok(output)
+    }
+
+    pub fn push(&mut self, output: impl OutputVisitor + 'static) {
+        self.outputs.push(Box::new(output));
+    }
+}
+
+impl OutputVisitor for MultiOutput {
+    fn visit_step_definitions(&mut self, definitions: &[StepDefinition]) {
+        for output in &mut self.outputs {
+            output.visit_step_definitions(definitions);
+        }
+    }
+
+    fn visit_start(&mut self) {
+        for output in &mut self.outputs {
+            output.visit_start();
+        }
+    }
+
+    fn visit_feature(&mut self, feature: &gherkin::Feature, path: &Path) {
+        for output in &mut self.outputs {
+            output.visit_feature(feature, path);
+        }
+    }
+
+    fn visit_feature_end(&mut self, feature: &gherkin::Feature, duration: Duration) {
+        for output in &mut self.outputs {
+            output.visit_feature_end(feature, duration);
+        }
+    }
+
+    fn visit_feature_error<'a>(&mut self, path: &Path, error: &gherkin::Error<'a>) {
+        for output in &mut self.outputs {
+            output.visit_feature_error(path, error);
+        }
+    }
+
+    fn visit_rule(&mut self, rule: &gherkin::Rule) {
+        for output in &mut self.outputs {
+            output.visit_rule(rule);
+        }
+    }
+
+    fn visit_rule_end(&mut self, rule: &gherkin::Rule) {
+        for output in &mut self.outputs {
+            output.visit_rule_end(rule);
+        }
+    }
+
+    fn visit_scenario(&mut self, rule: Option<&gherkin::Rule>, scenario: &gherkin::Scenario) {
+        for output in &mut self.outputs {
+            output.visit_scenario(rule, scenario);
+        }
+    }
+
+    fn visit_scenario_end(
+        &mut self,
+        rule: Option<&gherkin::Rule>,
+        scenario: &gherkin::Scenario,
+        duration: Duration,
+    ) {
+        for output in &mut self.outputs {
+            output.visit_scenario_end(rule, scenario, duration);
+        }
+    }
+
+    fn visit_hook(
+        &mut self,
+        rule: Option<&gherkin::Rule>,
+        scenario: &gherkin::Scenario,
+        hook: HookType,
+        duration: Duration,
+    ) {
+        for output in &mut self.outputs {
+            output.visit_hook(rule, scenario, hook, duration);
+        }
+    }
+
+    fn visit_scenario_skipped(
+        &mut self,
+        rule: Option<&gherkin::Rule>,
+        scenario: &gherkin::Scenario,
+    ) {
+        for output in &mut self.outputs {
+            output.visit_scenario_skipped(rule, scenario);
+        }
+    }
+
+    fn visit_step(
+        &mut self,
+        rule: Option<&gherkin::Rule>,
+        scenario: &gherkin::Scenario,
+        step: &gherkin::Step,
+    ) {
+        for output in &mut self.outputs {
+            output.visit_step(rule, scenario, step);
+        }
+    }
+
+    fn visit_logs(
+        &mut self,
+        rule: Option<&gherkin::Rule>,
+        scenario: &gherkin::Scenario,
+        step: Option<&gherkin::Step>,
+        records: &[LogRecord],
+    ) {
+        for output in &mut self.outputs {
+            output.visit_logs(rule, scenario, step, records);
+        }
+    }
+
+    fn visit_step_result(
+        &mut self,
+        rule: Option<&gherkin::Rule>,
+        scenario: &gherkin::Scenario,
+        step: &gherkin::Step,
+        result: &TestResult,
+        duration: Duration,
+    ) {
+        for output in &mut self.outputs {
+            output.visit_step_result(rule, scenario, step, result, duration);
+        }
+    }
+
+    fn visit_finish(&mut self, duration: Duration) {
+        for output in &mut self.outputs {
+            output.visit_finish(duration);
+        }
+    }
+}
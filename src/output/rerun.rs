@@ -0,0 +1,128 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::time::Duration;
+
+use gherkin;
+
+use super::relpath;
+use crate::OutputVisitor;
+use crate::TestResult;
+
+/// Writes the locations of failed scenarios, one `path:line` per line, to
+/// the file given as `--format rerun:<path>` or `--rerun-file <path>`.
+///
+/// It only ever writes to a file, so that the list is not mixed in with the
+/// terminal output.
+///
+/// Giving the file back as `@path` runs only those scenarios. Features that
+/// failed to parse are written as their path alone, so that all of their
+/// scenarios are run again.
+pub struct RerunOutput {
+    writer: Box<dyn Write>,
+    uri: String,
+    locations: Vec<String>,
+}
+
+impl RerunOutput {
+    /// Creates the output writing the locations to the file at `path`.
+    pub fn create(path: &Path) -> io::Result<RerunOutput> {
+        let file = File::create(path)?;
+        Ok(RerunOutput::new(Box::new(BufWriter::new(file))))
+    }
+
+    fn new(writer: Box<dyn Write>) -> RerunOutput {
+        RerunOutput {
+            writer,
+            uri: String::new(),
+            locations: vec![],
+        }
+    }
+
+    fn write_report(&mut self) -> io::Result<()> {
+        for location in &self.locations {
+            writeln!(self.writer, "{}", location)?;
+        }
+        self.writer.flush()
+    }
+}
+
+impl OutputVisitor for RerunOutput {
+    fn visit_start(&mut self) {}
+
+    fn visit_feature(&mut self, _feature: &gherkin::Feature, path: &Path) {
+        self.uri = relpath(path).to_string_lossy().to_string();
+    }
+
+    fn visit_feature_end(&mut self, _feature: &gherkin::Feature, _duration: Duration) {}
+
+    fn visit_feature_error<'a>(&mut self, path: &Path, _error: &gherkin::Error<'a>) {
+        self.locations
+            .push(relpath(path).to_string_lossy().to_string());
+    }
+
+    fn visit_rule(&mut self, _rule: &gherkin::Rule) {}
+
+    fn visit_rule_end(&mut self, _rule: &gherkin::Rule) {}
+
+    fn visit_scenario(&mut self, _rule: Option<&gherkin::Rule>, _scenario: &gherkin::Scenario) {}
+
+    fn visit_scenario_end(
+        &mut self,
+        _rule: Option<&gherkin::Rule>,
+        _scenario: &gherkin::Scenario,
+        _duration: Duration,
+    ) {
+    }
+
+    fn visit_scenario_skipped(
+        &mut self,
+        _rule: Option<&gherkin::Rule>,
+        _scenario: &gherkin::Scenario,
+    ) {
+    }
+
+    fn visit_step(
+        &mut self,
+        _rule: Option<&gherkin::Rule>,
+        _scenario: &gherkin::Scenario,
+        _step: &gherkin::Step,
+    ) {
+    }
+
+    fn visit_step_result(
+        &mut self,
+        _rule: Option<&gherkin::Rule>,
+        scenario: &gherkin::Scenario,
+        _step: &gherkin::Step,
+        result: &TestResult,
+        _duration: Duration,
+    ) {
+        // A scenario stops at its first failed step, so is only recorded once.
+        if let TestResult::Fail(_, _, _) = result {
+            self.locations
+                .push(format!("{}:{}", self.uri, scenario.position.0));
+        }
+    }
+
+    fn visit_finish(&mut self, _duration: Duration) {
+        self.write_report().expect("failed to write rerun file");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::output::{create_output, OutputConfig};
+
+    #[test]
+    fn needs_a_path() {
+        let error = create_output("rerun", &OutputConfig::default())
+            .err()
+            .unwrap();
+
+        assert_eq!(
+            error.to_string(),
+            "the rerun format only writes to a file, given as rerun:<path>"
+        );
+    }
+}
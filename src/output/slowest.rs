@@ -0,0 +1,173 @@
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::path::Path;
+use std::time::Duration;
+
+use gherkin;
+
+use super::{format_duration, relpath};
+use crate::OutputVisitor;
+use crate::StepDefinition;
+use crate::TestResult;
+
+/// Reports the slowest scenarios and steps once the run has finished, along
+/// with the step definitions that took the most time in total.
+pub struct SlowestOutput {
+    writer: Box<dyn Write>,
+    count: usize,
+    definitions: Vec<StepDefinition>,
+    uri: String,
+    scenarios: Vec<Timing>,
+    steps: Vec<Timing>,
+    // Total time and number of steps run, by step definition.
+    definition_totals: HashMap<String, (Duration, usize)>,
+}
+
+struct Timing {
+    location: String,
+    name: String,
+    duration: Duration,
+}
+
+impl SlowestOutput {
+    /// Creates a `SlowestOutput` listing the `count` slowest scenarios and
+    /// steps, and step definitions.
+    pub fn new(count: usize, writer: Box<dyn Write>) -> SlowestOutput {
+        SlowestOutput {
+            writer,
+            count,
+            definitions: vec![],
+            uri: String::new(),
+            scenarios: vec![],
+            steps: vec![],
+            definition_totals: HashMap::new(),
+        }
+    }
+
+    fn write_timings(&mut self, title: &str, timings: &mut [Timing]) -> io::Result<()> {
+        timings.sort_by_key(|timing| std::cmp::Reverse(timing.duration));
+
+        writeln!(self.writer, "{}:", title)?;
+        for timing in timings.iter().take(self.count) {
+            writeln!(
+                self.writer,
+                "  {:>9}  {} # {}",
+                format_duration(timing.duration),
+                timing.location,
+                timing.name
+            )?;
+        }
+        writeln!(self.writer)
+    }
+
+    fn write_report(&mut self) -> io::Result<()> {
+        let mut scenarios = std::mem::take(&mut self.scenarios);
+        self.write_timings("Slowest scenarios", &mut scenarios)?;
+
+        let mut steps = std::mem::take(&mut self.steps);
+        self.write_timings("Slowest steps", &mut steps)?;
+
+        let mut totals = self.definition_totals.iter().collect::<Vec<_>>();
+        totals.sort_by(|a, b| (b.1).0.cmp(&(a.1).0).then_with(|| a.0.cmp(b.0)));
+
+        writeln!(self.writer, "Step definitions by total time:")?;
+        for (id, (duration, count)) in totals.into_iter().take(self.count) {
+            writeln!(
+                self.writer,
+                "  {:>9}  {:>4} {}  {}",
+                format_duration(*duration),
+                count,
+                if *count == 1 { "step " } else { "steps" },
+                id
+            )?;
+        }
+        writeln!(self.writer)?;
+
+        self.writer.flush()
+    }
+}
+
+impl OutputVisitor for SlowestOutput {
+    fn visit_step_definitions(&mut self, definitions: &[StepDefinition]) {
+        self.definitions = definitions.to_vec();
+    }
+
+    fn visit_start(&mut self) {}
+
+    fn visit_feature(&mut self, _feature: &gherkin::Feature, path: &Path) {
+        self.uri = relpath(path).to_string_lossy().to_string();
+    }
+
+    fn visit_feature_end(&mut self, _feature: &gherkin::Feature, _duration: Duration) {}
+
+    fn visit_feature_error<'a>(&mut self, _path: &Path, _error: &gherkin::Error<'a>) {}
+
+    fn visit_rule(&mut self, _rule: &gherkin::Rule) {}
+
+    fn visit_rule_end(&mut self, _rule: &gherkin::Rule) {}
+
+    fn visit_scenario(&mut self, _rule: Option<&gherkin::Rule>, _scenario: &gherkin::Scenario) {}
+
+    fn visit_scenario_end(
+        &mut self,
+        _rule: Option<&gherkin::Rule>,
+        scenario: &gherkin::Scenario,
+        duration: Duration,
+    ) {
+        self.scenarios.push(Timing {
+            location: format!("{}:{}", self.uri, scenario.position.0),
+            name: scenario.name.clone(),
+            duration,
+        });
+    }
+
+    fn visit_scenario_skipped(
+        &mut self,
+        _rule: Option<&gherkin::Rule>,
+        _scenario: &gherkin::Scenario,
+    ) {
+    }
+
+    fn visit_step(
+        &mut self,
+        _rule: Option<&gherkin::Rule>,
+        _scenario: &gherkin::Scenario,
+        _step: &gherkin::Step,
+    ) {
+    }
+
+    fn visit_step_result(
+        &mut self,
+        _rule: Option<&gherkin::Rule>,
+        _scenario: &gherkin::Scenario,
+        step: &gherkin::Step,
+        result: &TestResult,
+        duration: Duration,
+    ) {
+        // Only steps that were run took any time.
+        if !matches!(result, TestResult::Pass | TestResult::Fail(_, _, _)) {
+            return;
+        }
+
+        self.steps.push(Timing {
+            location: format!("{}:{}", self.uri, step.position.0),
+            name: step.to_string(),
+            duration,
+        });
+
+        // The step's time goes to the definition that ran it.
+        if let Some(definition) = StepDefinition::find(&self.definitions, step) {
+            let total = self
+                .definition_totals
+                .entry(definition.id())
+                .or_insert((Duration::default(), 0));
+            total.0 += duration;
+            total.1 += 1;
+        }
+    }
+
+    fn visit_finish(&mut self, _duration: Duration) {
+        self.write_report()
+            .expect("failed to write slowest scenarios report");
+    }
+}
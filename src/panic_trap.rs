@@ -1,32 +1,215 @@
+use std::any::Any;
+use std::cell::{Cell, RefCell};
 use std::io::Read;
-use std::ops::Deref;
 use std::panic;
-use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
 
 use shh::{stderr, stdout};
 
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 pub struct PanicDetails {
     pub payload: String,
     pub location: String,
 }
 
 impl PanicDetails {
-    fn from_panic_info(info: &panic::PanicInfo) -> PanicDetails {
-        let payload = if let Some(s) = info.payload().downcast_ref::<String>() {
-            s.clone()
-        } else if let Some(s) = info.payload().downcast_ref::<&str>() {
-            s.deref().to_owned()
-        } else {
-            "Opaque panic payload".to_owned()
-        };
-
+    fn from_panic_info(info: &panic::PanicHookInfo) -> PanicDetails {
         let location = info
             .location()
             .map(|loc| format!("{}:{}:{}", loc.file(), loc.line(), loc.column()))
             .unwrap_or_else(|| "Unknown panic location".to_owned());
 
-        PanicDetails { payload, location }
+        PanicDetails {
+            payload: payload_string(info.payload()),
+            location,
+        }
+    }
+}
+
+fn payload_string(payload: &(dyn Any + Send)) -> String {
+    if let Some(s) = payload.downcast_ref::<String>() {
+        s.clone()
+    } else if let Some(s) = payload.downcast_ref::<&str>() {
+        (*s).to_owned()
+    } else {
+        "Opaque panic payload".to_owned()
+    }
+}
+
+type PanicHook = dyn Fn(&panic::PanicHookInfo) + Send + Sync + 'static;
+
+// The hook that was set before any trap was running, how many are, and the
+// address of the hook the traps set, to tell whether it was replaced since.
+struct HookState {
+    traps: usize,
+    previous: Option<Arc<PanicHook>>,
+    installed: usize,
+}
+
+static HOOK_STATE: Mutex<HookState> = Mutex::new(HookState {
+    traps: 0,
+    previous: None,
+    installed: 0,
+});
+
+// The scenario whose traps have stdout and stderr redirected, which is for the
+// whole process, and the signal that they no longer are.
+static CAPTURE_OWNER: Mutex<Option<u64>> = Mutex::new(None);
+static CAPTURE_ENDED: Condvar = Condvar::new();
+
+// Keys the captures of scenarios and of traps run outside any scenario.
+static NEXT_CAPTURE_KEY: AtomicU64 = AtomicU64::new(0);
+
+thread_local! {
+    // The scenario being run on this thread, or by the step that spawned it.
+    static SCENARIO: Cell<Option<u64>> = const { Cell::new(None) };
+    // How many traps are running on this thread.
+    static TRAP_DEPTH: Cell<usize> = const { Cell::new(0) };
+    // The panic caught on this thread, if any.
+    static LAST_PANIC: RefCell<Option<PanicDetails>> = const { RefCell::new(None) };
+}
+
+/// Sets the panic hook of the traps while one is running on any thread, and
+/// puts back the hook from before once none are.
+///
+/// Panics on threads that are not in a trap, such as ones spawned by a step,
+/// go to the previous hook as they would have otherwise.
+struct HookGuard;
+
+impl HookGuard {
+    fn enter() -> HookGuard {
+        let mut state = HOOK_STATE.lock().unwrap_or_else(|e| e.into_inner());
+
+        if state.traps == 0 {
+            let previous: Arc<PanicHook> = Arc::from(panic::take_hook());
+            state.previous = Some(previous.clone());
+
+            let hook: Box<PanicHook> = Box::new(move |info| {
+                if TRAP_DEPTH.with(Cell::get) > 0 {
+                    let details = PanicDetails::from_panic_info(info);
+                    LAST_PANIC.with(|last| *last.borrow_mut() = Some(details));
+                } else {
+                    previous(info);
+                }
+            });
+            state.installed = hook_address(&*hook);
+            panic::set_hook(hook);
+        }
+        state.traps += 1;
+        TRAP_DEPTH.with(|depth| depth.set(depth.get() + 1));
+
+        HookGuard
+    }
+}
+
+impl Drop for HookGuard {
+    fn drop(&mut self) {
+        TRAP_DEPTH.with(|depth| depth.set(depth.get() - 1));
+
+        let mut state = HOOK_STATE.lock().unwrap_or_else(|e| e.into_inner());
+        state.traps -= 1;
+        if state.traps == 0 {
+            let current = panic::take_hook();
+            let previous = state.previous.take();
+            match previous {
+                Some(previous) if hook_address(&*current) == state.installed => {
+                    panic::set_hook(Box::new(move |info| previous(info)));
+                }
+                // The program set a hook of its own while a trap was running,
+                // which stays.
+                _ => panic::set_hook(current),
+            }
+        }
+    }
+}
+
+fn hook_address(hook: &PanicHook) -> usize {
+    hook as *const PanicHook as *const () as usize
+}
+
+/// Keys the output captured on this thread by the scenario it runs, for as
+/// long as the guard is held.
+pub(crate) struct ScenarioGuard {
+    previous: Option<u64>,
+}
+
+impl ScenarioGuard {
+    pub(crate) fn enter() -> ScenarioGuard {
+        let key = NEXT_CAPTURE_KEY.fetch_add(1, Ordering::SeqCst);
+        ScenarioGuard {
+            previous: SCENARIO.with(|scenario| scenario.replace(Some(key))),
+        }
+    }
+}
+
+impl Drop for ScenarioGuard {
+    fn drop(&mut self) {
+        SCENARIO.with(|scenario| scenario.set(self.previous));
+    }
+}
+
+/// Spawns a thread whose output is captured with the scenario it is spawned
+/// from.
+///
+/// A thread spawned with `std::thread::spawn` while a step is capturing has
+/// its output captured with whichever scenario is capturing when it prints,
+/// which is only the one that spawned it while the step is still running.
+pub fn spawn<F, T>(f: F) -> thread::JoinHandle<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let scenario = SCENARIO.with(Cell::get);
+    thread::spawn(move || {
+        SCENARIO.with(|key| key.set(scenario));
+        f()
+    })
+}
+
+/// Redirects stdout and stderr for as long as the outermost guard is held.
+///
+/// They are shared by the whole process, so only one scenario captures at a
+/// time. A trap of another scenario waits for it to end, so that each keeps
+/// its own output. A trap of the same scenario, or on a thread that is not
+/// running one, such as a thread the capturing step spawned and may be
+/// waiting on, leaves its output to the capturing trap instead.
+struct CaptureGuard {
+    is_outermost: bool,
+}
+
+impl CaptureGuard {
+    fn enter() -> CaptureGuard {
+        let scenario = SCENARIO.with(Cell::get);
+        let mut owner = CAPTURE_OWNER.lock().unwrap_or_else(|e| e.into_inner());
+        loop {
+            match *owner {
+                None => {
+                    *owner = Some(
+                        scenario.unwrap_or_else(|| NEXT_CAPTURE_KEY.fetch_add(1, Ordering::SeqCst)),
+                    );
+                    return CaptureGuard { is_outermost: true };
+                }
+                Some(key) if scenario.is_none_or(|scenario| scenario == key) => {
+                    return CaptureGuard {
+                        is_outermost: false,
+                    };
+                }
+                Some(_) => {
+                    owner = CAPTURE_ENDED.wait(owner).unwrap_or_else(|e| e.into_inner());
+                }
+            }
+        }
+    }
+}
+
+impl Drop for CaptureGuard {
+    fn drop(&mut self) {
+        if self.is_outermost {
+            *CAPTURE_OWNER.lock().unwrap_or_else(|e| e.into_inner()) = None;
+            CAPTURE_ENDED.notify_all();
+        }
     }
 }
 
@@ -46,6 +229,11 @@ impl<T> PanicTrap<T> {
     }
 
     fn run_quietly<F: FnOnce() -> T>(f: F) -> PanicTrap<T> {
+        let capture = CaptureGuard::enter();
+        if !capture.is_outermost {
+            return PanicTrap::run_loudly(f);
+        }
+
         let mut stdout = stdout().expect("Failed to capture stdout");
         let mut stderr = stderr().expect("Failed to capture stderr");
 
@@ -58,31 +246,214 @@ impl<T> PanicTrap<T> {
     }
 
     fn run_loudly<F: FnOnce() -> T>(f: F) -> PanicTrap<T> {
-        let last_panic = Arc::new(Mutex::new(None));
-
-        panic::set_hook({
-            let last_panic = last_panic.clone();
-
-            Box::new(move |info| {
-                *last_panic.lock().expect("Last panic mutex poisoned") =
-                    Some(PanicDetails::from_panic_info(info));
-            })
-        });
-
-        let result = panic::catch_unwind(panic::AssertUnwindSafe(f));
-
-        panic::take_hook();
+        LAST_PANIC.with(|last| last.borrow_mut().take());
+        let result = {
+            let _hook = HookGuard::enter();
+            panic::catch_unwind(panic::AssertUnwindSafe(f))
+        };
 
         PanicTrap {
-            result: result.map_err(|_| {
-                last_panic
-                    .lock()
-                    .expect("Last panic mutex poisoned")
-                    .take()
-                    .expect("Panic occurred but no panic details were set")
+            result: result.map_err(|payload| {
+                // Without details, such as when a step replaced the panic hook,
+                // the payload is all there is.
+                LAST_PANIC
+                    .with(|last| last.borrow_mut().take())
+                    .unwrap_or_else(|| PanicDetails {
+                        payload: payload_string(&*payload),
+                        location: "Unknown panic location".to_owned(),
+                    })
             }),
             stdout: Vec::new(),
             stderr: Vec::new(),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Write};
+    use std::panic;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+
+    use super::{PanicTrap, ScenarioGuard};
+    use crate::testing;
+
+    // Calls to the hook set by `with_counting_hook`.
+    static HOOK_CALLS: AtomicUsize = AtomicUsize::new(0);
+
+    /// Runs `f` with a panic hook that counts its calls, as a program's own
+    /// hook would be, and puts back the hook from before.
+    fn with_counting_hook(f: impl FnOnce()) {
+        let _lock = testing::lock();
+        let original = panic::take_hook();
+        panic::set_hook(Box::new(|_| {
+            HOOK_CALLS.fetch_add(1, Ordering::SeqCst);
+        }));
+
+        let result = panic::catch_unwind(panic::AssertUnwindSafe(f));
+        panic::set_hook(original);
+        if let Err(payload) = result {
+            panic::resume_unwind(payload);
+        }
+    }
+
+    fn panic_outside_a_trap() {
+        assert!(thread::spawn(|| panic!("outside")).join().is_err());
+    }
+
+    #[test]
+    fn catches_panics_with_their_details() {
+        with_counting_hook(|| {
+            let calls = HOOK_CALLS.load(Ordering::SeqCst);
+            let trap = PanicTrap::run(false, || panic!("it failed"));
+
+            let details = trap.result.err().unwrap();
+            assert_eq!(details.payload, "it failed");
+            assert!(details.location.starts_with(file!()));
+            assert_eq!(HOOK_CALLS.load(Ordering::SeqCst), calls);
+        });
+    }
+
+    #[test]
+    fn puts_back_the_hook_it_replaced() {
+        with_counting_hook(|| {
+            let _ = PanicTrap::run(false, || panic!("caught"));
+            let nested = PanicTrap::run(false, || PanicTrap::run(false, || panic!("nested")));
+            assert!(nested.result.unwrap().result.is_err());
+
+            let calls = HOOK_CALLS.load(Ordering::SeqCst);
+            panic_outside_a_trap();
+            assert_eq!(HOOK_CALLS.load(Ordering::SeqCst), calls + 1);
+        });
+    }
+
+    #[test]
+    fn keeps_a_hook_set_while_a_trap_runs() {
+        with_counting_hook(|| {
+            let _ = PanicTrap::run(false, || panic::set_hook(Box::new(|_| {})));
+
+            let calls = HOOK_CALLS.load(Ordering::SeqCst);
+            panic_outside_a_trap();
+            assert_eq!(HOOK_CALLS.load(Ordering::SeqCst), calls);
+        });
+    }
+
+    #[test]
+    fn leaves_panics_of_other_threads_to_the_hook() {
+        with_counting_hook(|| {
+            let calls = HOOK_CALLS.load(Ordering::SeqCst);
+            let trap = PanicTrap::run(false, panic_outside_a_trap);
+
+            assert!(trap.result.is_ok());
+            assert_eq!(HOOK_CALLS.load(Ordering::SeqCst), calls + 1);
+        });
+    }
+
+    #[test]
+    fn catches_the_panics_of_each_thread_in_its_own_trap() {
+        with_counting_hook(|| {
+            let threads = (0..4)
+                .map(|n| {
+                    thread::spawn(move || {
+                        PanicTrap::run(false, move || panic!("thread {}", n))
+                            .result
+                            .err()
+                            .unwrap()
+                            .payload
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            for (n, thread) in threads.into_iter().enumerate() {
+                assert_eq!(thread.join().unwrap(), format!("thread {}", n));
+            }
+        });
+    }
+
+    #[test]
+    fn captures_stdout_and_stderr() {
+        let _lock = testing::lock();
+        let trap = PanicTrap::run(true, || {
+            writeln!(io::stdout(), "to stdout").unwrap();
+            writeln!(io::stderr(), "to stderr").unwrap();
+            io::stdout().flush().unwrap();
+        });
+
+        assert_eq!(trap.stdout, b"to stdout\n");
+        assert_eq!(trap.stderr, b"to stderr\n");
+    }
+
+    #[test]
+    fn captures_traps_on_threads_a_capturing_trap_waits_on() {
+        let _lock = testing::lock();
+        let (sender, receiver) = mpsc::channel();
+
+        // Run apart, so that the test fails rather than hangs if they wait on
+        // each other.
+        thread::spawn(move || {
+            let trap = PanicTrap::run(true, || {
+                thread::spawn(|| {
+                    PanicTrap::run(true, || {
+                        writeln!(io::stdout(), "from the inner trap").unwrap();
+                        io::stdout().flush().unwrap();
+                        panic!("inner")
+                    })
+                    .result
+                    .is_err()
+                })
+                .join()
+                .unwrap()
+            });
+            let _ = sender.send(trap);
+        });
+
+        let trap = receiver.recv_timeout(Duration::from_secs(10)).unwrap();
+        assert!(trap.result.unwrap());
+        assert_eq!(trap.stdout, b"from the inner trap\n");
+    }
+
+    #[test]
+    fn keeps_the_output_of_scenarios_run_at_the_same_time_apart() {
+        let _lock = testing::lock();
+        let (started, has_started) = mpsc::channel();
+        let (sender, receiver) = mpsc::channel();
+
+        let first_sender = sender.clone();
+        thread::spawn(move || {
+            let _scenario = ScenarioGuard::enter();
+            let trap = PanicTrap::run(true, || {
+                started.send(()).unwrap();
+                thread::sleep(Duration::from_millis(100));
+                // A thread spawned by the step is captured with its scenario.
+                super::spawn(|| {
+                    writeln!(io::stdout(), "first").unwrap();
+                    io::stdout().flush().unwrap();
+                })
+                .join()
+                .unwrap();
+            });
+            let _ = first_sender.send((1, trap.stdout));
+        });
+        thread::spawn(move || {
+            let _scenario = ScenarioGuard::enter();
+            has_started.recv().unwrap();
+            let trap = PanicTrap::run(true, || {
+                writeln!(io::stdout(), "second").unwrap();
+                io::stdout().flush().unwrap();
+            });
+            let _ = sender.send((2, trap.stdout));
+        });
+
+        let mut outputs = (0..2)
+            .map(|_| receiver.recv_timeout(Duration::from_secs(10)).unwrap())
+            .collect::<Vec<_>>();
+        outputs.sort();
+        assert_eq!(
+            outputs,
+            vec![(1, b"first\n".to_vec()), (2, b"second\n".to_vec())]
+        );
+    }
+}
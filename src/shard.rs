@@ -0,0 +1,318 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use gherkin::{Feature, Scenario};
+use serde::Deserialize;
+
+use crate::cli::Shard;
+use crate::docstring;
+use crate::outline;
+use crate::output::relpath;
+
+/// The key a scenario is assigned to a shard by, which is the same on every
+/// machine run from the same directory.
+pub(crate) fn key(path: &Path, scenario: &Scenario) -> String {
+    format!("{}:{}", relpath(path).display(), scenario.position.0)
+}
+
+/// The keys of the scenarios of `feature_files` that belong to `shard`.
+///
+/// Every scenario is weighed by its duration in the previous report, or the
+/// average duration when it is not in it, and given in turn to the shard with
+/// the least to run so far. Scenarios of equal weight are taken in an order
+/// set by a hash of their key, so that every shard comes to the same split.
+pub(crate) fn assign(shard: &Shard, feature_files: &[PathBuf]) -> HashSet<String> {
+    let keys = feature_files
+        .iter()
+        .flat_map(|path| scenario_keys(path))
+        .collect::<Vec<_>>();
+
+    let known = keys
+        .iter()
+        .filter_map(|key| shard.durations.get(key))
+        .collect::<Vec<_>>();
+    let average = match known.len() {
+        0 => Duration::from_nanos(1),
+        n => known.iter().copied().sum::<Duration>() / n as u32,
+    };
+
+    let mut weighted = keys
+        .into_iter()
+        .map(|key| {
+            let weight = shard.durations.get(&key).copied().unwrap_or(average);
+            (weight, fnv1a(&key), key)
+        })
+        .collect::<Vec<_>>();
+    weighted.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| (a.1, &a.2).cmp(&(b.1, &b.2))));
+
+    // The total weight and number of scenarios given to each shard.
+    let mut loads = vec![(Duration::ZERO, 0usize); shard.count];
+    let mut selected = HashSet::new();
+    for (weight, _, key) in weighted {
+        let (index, load) = loads
+            .iter_mut()
+            .enumerate()
+            .min_by_key(|(index, load)| (load.0, load.1, *index))
+            .expect("a shard count of at least one");
+        load.0 += weight;
+        load.1 += 1;
+        if index + 1 == shard.index {
+            selected.insert(key);
+        }
+    }
+
+    selected
+}
+
+/// Whether any of `scenarios` of the feature file at `path`, or of the
+/// scenarios they expand to, is among the `assigned` keys of a shard.
+pub(crate) fn any_assigned<'a>(
+    assigned: &HashSet<String>,
+    path: &Path,
+    scenarios: impl IntoIterator<Item = &'a Scenario>,
+) -> bool {
+    scenarios
+        .into_iter()
+        .flat_map(outline::expand)
+        .any(|scenario| assigned.contains(&key(path, &scenario)))
+}
+
+fn scenario_keys(path: &Path) -> Vec<String> {
+    let buffer = match fs::read_to_string(path) {
+        Ok(buffer) => buffer,
+        Err(_) => return vec![],
+    };
+    let (buffer, _) = docstring::strip_content_types(&buffer);
+
+    // A feature that fails to parse is reported when it is run.
+    let feature = match Feature::try_from(&buffer) {
+        Ok(feature) => feature,
+        Err(_) => return vec![],
+    };
+
+    feature
+        .scenarios
+        .iter()
+        .chain(feature.rules.iter().flat_map(|rule| rule.scenarios.iter()))
+        .flat_map(outline::expand)
+        .map(|scenario| key(path, &scenario))
+        .collect()
+}
+
+// 64-bit FNV-1a, which unlike `DefaultHasher` is the same on every platform
+// and release.
+fn fnv1a(s: &str) -> u64 {
+    s.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+#[derive(Deserialize)]
+struct ReportFeature {
+    uri: String,
+    #[serde(default)]
+    elements: Vec<ReportElement>,
+}
+
+#[derive(Deserialize)]
+struct ReportElement {
+    #[serde(rename = "type", default)]
+    ty: String,
+    line: usize,
+    #[serde(default)]
+    steps: Vec<ReportStep>,
+}
+
+#[derive(Deserialize)]
+struct ReportStep {
+    result: ReportResult,
+}
+
+#[derive(Deserialize)]
+struct ReportResult {
+    /// In nanoseconds.
+    duration: Option<u64>,
+}
+
+/// The duration of each scenario in a Cucumber JSON report, by key.
+pub(crate) fn read_durations(path: &Path) -> Result<HashMap<String, Duration>, String> {
+    let contents = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let features: Vec<ReportFeature> =
+        serde_json::from_str(&contents).map_err(|e| e.to_string())?;
+
+    Ok(features
+        .iter()
+        .flat_map(|feature| {
+            feature
+                .elements
+                .iter()
+                .filter(|element| element.ty != "background")
+                .map(move |element| {
+                    let nanos = element
+                        .steps
+                        .iter()
+                        .filter_map(|step| step.result.duration)
+                        .sum();
+                    (
+                        format!("{}:{}", feature.uri, element.line),
+                        Duration::from_nanos(nanos),
+                    )
+                })
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{HashMap, HashSet};
+    use std::fs;
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    use super::{assign, read_durations};
+    use crate::cli::Shard;
+    use crate::testing;
+
+    // Five scenarios, at lines 3, 6, 9, 12 and 15.
+    const SOURCE: &str = "\
+Feature: Sharding
+
+  Scenario: one
+    Given a step that passes
+
+  Scenario: two
+    Given a step that passes
+
+  Scenario: three
+    Given a step that passes
+
+  Scenario: four
+    Given a step that passes
+
+  Scenario: five
+    Given a step that passes
+";
+
+    fn shard(index: usize, count: usize, durations: &[(&str, u64)]) -> Shard {
+        Shard {
+            index,
+            count,
+            durations: durations
+                .iter()
+                .map(|(key, secs)| (key.to_string(), Duration::from_secs(*secs)))
+                .collect(),
+        }
+    }
+
+    fn keys(name: &str, lines: &[usize]) -> HashSet<String> {
+        lines
+            .iter()
+            .map(|line| format!("target/test-features/{}.feature:{}", name, line))
+            .collect()
+    }
+
+    #[test]
+    fn splits_scenarios_the_same_way_every_time() {
+        let files = vec![
+            testing::feature_file("shard-a", SOURCE),
+            testing::feature_file("shard-b", SOURCE),
+        ];
+
+        let shards = (1..=3)
+            .map(|index| assign(&shard(index, 3, &[]), &files))
+            .collect::<Vec<_>>();
+        for (index, assigned) in shards.iter().enumerate() {
+            assert_eq!(&assign(&shard(index + 1, 3, &[]), &files), assigned);
+            // Ten scenarios of equal weight, spread as evenly as they go.
+            assert!(assigned.len() == 3 || assigned.len() == 4);
+        }
+
+        let mut all = HashSet::new();
+        for assigned in &shards {
+            assert!(assigned.is_disjoint(&all));
+            all.extend(assigned.iter().cloned());
+        }
+        let mut expected = keys("shard-a", &[3, 6, 9, 12, 15]);
+        expected.extend(keys("shard-b", &[3, 6, 9, 12, 15]));
+        assert_eq!(all, expected);
+    }
+
+    #[test]
+    fn gives_the_longest_scenarios_out_first() {
+        let files = vec![testing::feature_file("shard-lpt", SOURCE)];
+        let durations = [
+            ("target/test-features/shard-lpt.feature:3", 5),
+            ("target/test-features/shard-lpt.feature:6", 4),
+            ("target/test-features/shard-lpt.feature:9", 3),
+            ("target/test-features/shard-lpt.feature:12", 3),
+            ("target/test-features/shard-lpt.feature:15", 3),
+        ];
+
+        // The 5 and the 4 go to a shard each, then the threes in turn to the
+        // one with the least: 4 + 3, 5 + 3, then 7 + 3.
+        let first = assign(&shard(1, 2, &durations), &files);
+        let second = assign(&shard(2, 2, &durations), &files);
+        assert!(first.contains(durations[0].0) && first.len() == 2);
+        assert!(second.contains(durations[1].0) && second.len() == 3);
+    }
+
+    #[test]
+    fn weighs_scenarios_missing_from_the_report_by_the_average() {
+        let files = vec![testing::feature_file("shard-average", SOURCE)];
+        let durations = [
+            ("target/test-features/shard-average.feature:3", 10),
+            ("target/test-features/shard-average.feature:6", 2),
+        ];
+
+        // The three others weigh 6 each: the 10 and a 6 go to a shard each,
+        // then 6 + 6, 10 + 6, and the 2 last, to the shard with 12.
+        let first = assign(&shard(1, 2, &durations), &files);
+        let second = assign(&shard(2, 2, &durations), &files);
+        assert!(first.contains(durations[0].0) && first.len() == 2);
+        assert!(second.contains(durations[1].0) && second.len() == 3);
+    }
+
+    #[test]
+    fn reads_the_durations_of_scenarios_in_a_report() {
+        let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("target/test-features");
+        let report = dir.join("shard-report.json");
+        fs::write(
+            &report,
+            r#"[{"uri": "a.feature", "elements": [
+                {"type": "background", "line": 2, "steps": [{"result": {"duration": 7}}]},
+                {"type": "scenario", "line": 4, "steps": [
+                    {"result": {"duration": 1000}},
+                    {"result": {"status": "skipped"}},
+                    {"result": {"duration": 500}}
+                ]},
+                {"type": "scenario", "line": 9}
+            ]}]"#,
+        )
+        .unwrap();
+
+        let mut expected = HashMap::new();
+        expected.insert("a.feature:4".to_string(), Duration::from_nanos(1500));
+        expected.insert("a.feature:9".to_string(), Duration::ZERO);
+        assert_eq!(read_durations(&report).unwrap(), expected);
+    }
+
+    #[test]
+    fn fails_to_read_malformed_reports() {
+        let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("target/test-features");
+        let report = dir.join("shard-malformed.json");
+
+        for contents in &[
+            "not json",
+            r#"{"uri": "a.feature"}"#,
+            r#"[{"elements": []}]"#,
+            r#"[{"uri": "a.feature", "elements": [{"line": "4"}]}]"#,
+        ] {
+            fs::write(&report, contents).unwrap();
+            assert!(read_durations(&report).is_err(), "{}", contents);
+        }
+
+        assert!(read_durations(&dir.join("shard-missing.json")).is_err());
+    }
+}
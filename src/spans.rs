@@ -0,0 +1,286 @@
+//! `tracing` spans for the features, rules, scenarios and steps being run,
+//! emitted with the `tracing` feature.
+
+use std::path::Path;
+
+use crate::TestResult;
+
+/// An entered span, which is left when dropped. Without the `tracing`
+/// feature, it is nothing at all.
+pub(crate) struct Span {
+    #[cfg(feature = "tracing")]
+    span: tracing::span::EnteredSpan,
+}
+
+#[cfg(feature = "tracing")]
+mod fields {
+    use std::path::Path;
+
+    use crate::output::relpath;
+    use crate::TestResult;
+
+    pub fn location(path: &Path, position: (usize, usize)) -> String {
+        format!("{}:{}", relpath(path).display(), position.0)
+    }
+
+    pub fn tags(tags: &Option<Vec<String>>) -> String {
+        tags.as_deref().unwrap_or_default().join(" ")
+    }
+
+    /// The status of a step result, as the isolated runs report it.
+    pub fn status(result: &TestResult) -> &'static str {
+        match result {
+            TestResult::Pass => "passed",
+            TestResult::Fail(_, _, _) => "failed",
+            TestResult::Skipped => "skipped",
+            TestResult::Unimplemented => "unimplemented",
+            TestResult::MutexPoisoned => "poisoned",
+        }
+    }
+}
+
+#[cfg(feature = "tracing")]
+impl Span {
+    fn enter(span: tracing::Span) -> Span {
+        Span {
+            span: span.entered(),
+        }
+    }
+
+    pub(crate) fn feature(path: &Path, feature: &gherkin::Feature) -> Span {
+        Span::enter(tracing::info_span!(
+            "feature",
+            name = %feature.name,
+            tags = %fields::tags(&feature.tags),
+            location = %fields::location(path, feature.position),
+            result = tracing::field::Empty,
+        ))
+    }
+
+    pub(crate) fn rule(path: &Path, rule: &gherkin::Rule) -> Span {
+        Span::enter(tracing::info_span!(
+            "rule",
+            name = %rule.name,
+            tags = %fields::tags(&rule.tags),
+            location = %fields::location(path, rule.position),
+            result = tracing::field::Empty,
+        ))
+    }
+
+    pub(crate) fn scenario(path: &Path, scenario: &gherkin::Scenario) -> Span {
+        Span::enter(tracing::info_span!(
+            "scenario",
+            name = %scenario.name,
+            tags = %fields::tags(&scenario.tags),
+            location = %fields::location(path, scenario.position),
+            result = tracing::field::Empty,
+        ))
+    }
+
+    pub(crate) fn step(path: &Path, step: &gherkin::Step) -> Span {
+        Span::enter(tracing::info_span!(
+            "step",
+            name = %step.to_string(),
+            location = %fields::location(path, step.position),
+            result = tracing::field::Empty,
+        ))
+    }
+
+    /// Records whether all the steps of a feature, rule or scenario passed.
+    pub(crate) fn record_success(&self, is_success: bool) {
+        let result = if is_success { "passed" } else { "failed" };
+        self.span.record("result", result);
+    }
+
+    /// Records that a scenario was skipped, for a step it has no definition
+    /// of.
+    pub(crate) fn record_skipped(&self) {
+        self.span.record("result", "skipped");
+    }
+
+    pub(crate) fn record_result(&self, result: &TestResult) {
+        self.span.record("result", fields::status(result));
+    }
+}
+
+#[cfg(not(feature = "tracing"))]
+impl Span {
+    pub(crate) fn feature(_path: &Path, _feature: &gherkin::Feature) -> Span {
+        Span {}
+    }
+
+    pub(crate) fn rule(_path: &Path, _rule: &gherkin::Rule) -> Span {
+        Span {}
+    }
+
+    pub(crate) fn scenario(_path: &Path, _scenario: &gherkin::Scenario) -> Span {
+        Span {}
+    }
+
+    pub(crate) fn step(_path: &Path, _step: &gherkin::Step) -> Span {
+        Span {}
+    }
+
+    pub(crate) fn record_success(&self, _is_success: bool) {}
+
+    pub(crate) fn record_skipped(&self) {}
+
+    pub(crate) fn record_result(&self, _result: &TestResult) {}
+}
+
+#[cfg(all(test, feature = "tracing"))]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::fmt;
+    use std::io;
+    use std::sync::{Arc, Mutex};
+
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::{Event, Metadata, Subscriber};
+
+    use crate::output::json::JsonOutput;
+    use crate::output::ReportOutput;
+    use crate::{testing, StepType, Steps, World};
+
+    #[derive(Default)]
+    struct TestWorld;
+
+    impl World for TestWorld {}
+
+    struct Span {
+        name: &'static str,
+        parent: Option<usize>,
+        fields: BTreeMap<String, String>,
+    }
+
+    impl Visit for Span {
+        fn record_str(&mut self, field: &Field, value: &str) {
+            self.fields
+                .insert(field.name().to_string(), value.to_string());
+        }
+
+        fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+            self.fields
+                .insert(field.name().to_string(), format!("{:?}", value));
+        }
+    }
+
+    /// Records every span with its fields, and the span it was entered in.
+    #[derive(Default)]
+    struct Recorder {
+        spans: Mutex<Vec<Span>>,
+        entered: Mutex<Vec<usize>>,
+    }
+
+    impl Subscriber for Recorder {
+        fn enabled(&self, _metadata: &Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, attributes: &Attributes<'_>) -> Id {
+            let mut span = Span {
+                name: attributes.metadata().name(),
+                parent: self.entered.lock().unwrap().last().copied(),
+                fields: BTreeMap::new(),
+            };
+            attributes.record(&mut span);
+
+            let mut spans = self.spans.lock().unwrap();
+            spans.push(span);
+            Id::from_u64(spans.len() as u64)
+        }
+
+        fn record(&self, id: &Id, values: &Record<'_>) {
+            let mut spans = self.spans.lock().unwrap();
+            values.record(&mut spans[id.into_u64() as usize - 1]);
+        }
+
+        fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+        fn event(&self, _event: &Event<'_>) {}
+
+        fn enter(&self, id: &Id) {
+            self.entered
+                .lock()
+                .unwrap()
+                .push(id.into_u64() as usize - 1);
+        }
+
+        fn exit(&self, _id: &Id) {
+            self.entered.lock().unwrap().pop();
+        }
+    }
+
+    #[test]
+    fn nests_steps_in_scenarios_in_rules_in_features() {
+        let mut steps = Steps::<TestWorld>::default();
+        steps.add_normal(StepType::Given, "a step that passes", |_, _| {});
+        steps.add_normal(StepType::Then, "a step that passes", |_, _| {});
+        steps.add_normal(StepType::When, "a step that fails", |_, _| {
+            panic!("it failed")
+        });
+        let path = testing::feature_file(
+            "spans",
+            "\
+@spanned
+Feature: Spans
+
+  Scenario: passing
+    Given a step that passes
+
+  Rule: A rule
+
+    @failing
+    Scenario: failing
+      When a step that fails
+      Then a step that passes
+",
+        );
+
+        let recorder = Arc::new(Recorder::default());
+        let mut output = JsonOutput::new(Box::new(io::sink()));
+        let _lock = testing::lock();
+        tracing::subscriber::with_default(recorder.clone(), || {
+            steps.run(vec![path], None, None, testing::options(), &mut output);
+        });
+
+        let spans = recorder.spans.lock().unwrap();
+        let path = |mut index: usize| {
+            let mut names = vec![spans[index].name];
+            while let Some(parent) = spans[index].parent {
+                names.insert(0, spans[parent].name);
+                index = parent;
+            }
+            names.join("/")
+        };
+        let described = spans
+            .iter()
+            .enumerate()
+            .map(|(index, span)| {
+                let field = |name: &str| span.fields.get(name).cloned().unwrap_or_default();
+                format!(
+                    "{} {} [{}] {} {}",
+                    path(index),
+                    field("name"),
+                    field("tags"),
+                    field("location").trim_start_matches("target/test-features/"),
+                    field("result")
+                )
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            described,
+            vec![
+                "feature Spans [@spanned] spans.feature:2 failed",
+                "feature/scenario passing [] spans.feature:4 passed",
+                "feature/scenario/step Given a step that passes [] spans.feature:5 passed",
+                "feature/rule A rule [] spans.feature:7 failed",
+                "feature/rule/scenario failing [@failing] spans.feature:10 failed",
+                "feature/rule/scenario/step When a step that fails [] spans.feature:11 failed",
+                "feature/rule/scenario/step Then a step that passes [] spans.feature:12 skipped",
+            ]
+        );
+    }
+}
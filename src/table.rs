@@ -0,0 +1,149 @@
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::fmt;
+
+use gherkin::Table;
+use serde::de::value::MapDeserializer;
+use serde::Deserialize;
+
+use crate::capture::ValueDeserializer;
+
+/// An error converting a data table, with the location of the offending row
+/// and column in the .feature file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TableError {
+    /// The line of the offending row in the .feature file.
+    pub line: usize,
+    /// The header of the offending column, if the error is not for the whole row.
+    pub column: Option<String>,
+    /// A description of what went wrong.
+    pub message: String,
+}
+
+impl fmt::Display for TableError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.column {
+            Some(column) => write!(
+                f,
+                "line {}, column `{}`: {}",
+                self.line, column, self.message
+            ),
+            None => write!(f, "line {}: {}", self.line, self.message),
+        }
+    }
+}
+
+impl std::error::Error for TableError {}
+
+/// Conversions of a step's data table into more useful shapes.
+pub trait TableExt {
+    /// Deserializes each row into a `T`, using the header as field names.
+    fn rows_as<'de, T: Deserialize<'de>>(&'de self) -> Result<Vec<T>, TableError>;
+
+    /// Each row as a map from header to value.
+    fn hashes(&self) -> Vec<HashMap<String, String>>;
+
+    /// A two column table of keys and values as a map. The header row is
+    /// treated as the first key and value.
+    fn rows_hash(&self) -> Result<HashMap<String, String>, TableError>;
+
+    /// The table with its rows and columns swapped, so that the first column
+    /// becomes the header.
+    fn transpose(&self) -> Table;
+}
+
+impl TableExt for Table {
+    fn rows_as<'de, T: Deserialize<'de>>(&'de self) -> Result<Vec<T>, TableError> {
+        self.rows
+            .iter()
+            .enumerate()
+            .map(|(n, row)| {
+                // Tracks the column being deserialized, which is the one at
+                // fault if an error occurs before the row runs out of columns.
+                let column = Cell::new(None);
+                let values = self
+                    .header
+                    .iter()
+                    .zip(row.iter())
+                    .enumerate()
+                    .map(|(i, (header, value))| {
+                        column.set(Some(i));
+                        (header.as_str(), ValueDeserializer(value.as_str()))
+                    })
+                    .chain(std::iter::from_fn(|| {
+                        column.set(None);
+                        None
+                    }));
+
+                T::deserialize(MapDeserializer::new(values)).map_err(|e| TableError {
+                    line: row_line(self, n),
+                    column: column.get().map(|i| self.header[i].clone()),
+                    message: e.to_string(),
+                })
+            })
+            .collect()
+    }
+
+    fn hashes(&self) -> Vec<HashMap<String, String>> {
+        self.rows
+            .iter()
+            .map(|row| {
+                self.header
+                    .iter()
+                    .cloned()
+                    .zip(row.iter().cloned())
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn rows_hash(&self) -> Result<HashMap<String, String>, TableError> {
+        if self.header.len() != 2 {
+            return Err(TableError {
+                line: header_line(self),
+                column: None,
+                message: format!(
+                    "expected a table of 2 columns, found {} columns",
+                    self.header.len()
+                ),
+            });
+        }
+
+        Ok(std::iter::once(&self.header)
+            .chain(self.rows.iter())
+            .map(|row| (row[0].clone(), row[1].clone()))
+            .collect())
+    }
+
+    fn transpose(&self) -> Table {
+        let columns = (0..self.header.len()).map(|i| {
+            std::iter::once(&self.header)
+                .chain(self.rows.iter())
+                .map(|row| row[i].clone())
+                .collect::<Vec<_>>()
+        });
+
+        let mut rows = columns.collect::<Vec<_>>();
+        let header = if rows.is_empty() {
+            vec![]
+        } else {
+            rows.remove(0)
+        };
+
+        Table {
+            header,
+            rows,
+            position: self.position,
+        }
+    }
+}
+
+// The table's position is that of the line break ending the step line, so the
+// header is on the line after it.
+pub(crate) fn header_line(table: &Table) -> usize {
+    table.position.0 + 1
+}
+
+pub(crate) fn row_line(table: &Table, row: usize) -> usize {
+    header_line(table) + 1 + row
+}
@@ -0,0 +1,45 @@
+//! Feature files and options for the unit tests that need them.
+
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Mutex, MutexGuard};
+
+use crate::cli::{CliOptions, Order};
+use crate::output::{ColorMode, Verbosity};
+
+/// Writes `source` to a feature file named `name`, relative to the crate so
+/// that outputs report it as `target/test-features/<name>.feature`.
+pub(crate) fn feature_file(name: &str, source: &str) -> PathBuf {
+    let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("target/test-features");
+    fs::create_dir_all(&dir).unwrap();
+
+    let path = dir.join(format!("{}.feature", name));
+    fs::write(&path, source).unwrap();
+    path
+}
+
+pub(crate) fn options() -> CliOptions {
+    CliOptions {
+        feature: None,
+        filter: None,
+        tag: None,
+        suppress_output: false,
+        formats: vec![],
+        verbosity: Verbosity::Normal,
+        color: ColorMode::Never,
+        report_slowest: None,
+        width: Some(0),
+        locations: None,
+        order: Order::Defined,
+        shard: None,
+        isolate: None,
+        is_isolated_child: false,
+    }
+}
+
+/// Held by tests that run steps, which share the panic hook, stdout and
+/// stderr of the process, while tests run in parallel.
+pub(crate) fn lock() -> MutexGuard<'static, ()> {
+    static LOCK: Mutex<()> = Mutex::new(());
+    LOCK.lock().unwrap_or_else(|e| e.into_inner())
+}
@@ -1,6 +1,16 @@
-#![allow(clippy::assertions_on_constants)]
+#![allow(
+    clippy::assertions_on_constants,
+    clippy::derivable_impls,
+    clippy::print_literal
+)]
 
-use cucumber_rust::{after, before, cucumber, World};
+use std::panic;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use cucumber_rust::output::register_output;
+use cucumber_rust::{after, before, cucumber, gherkin, OutputVisitor, TestResult, World};
 
 pub struct MyWorld {
     pub thing: bool,
@@ -16,13 +26,109 @@ impl Default for MyWorld {
 
 #[cfg(test)]
 mod basic {
-    use cucumber_rust::steps;
+    use std::collections::HashMap;
+
+    use cucumber_rust::{steps, DocstringExt, TableExt};
+    use serde::Deserialize;
+
+    #[derive(Deserialize)]
+    struct Basket {
+        owner: String,
+        count: usize,
+        colour: Option<String>,
+    }
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    struct Row {
+        name: String,
+        count: usize,
+    }
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    struct Config {
+        name: String,
+        retries: u32,
+    }
+
+    #[derive(Deserialize, Debug)]
+    struct BadRow {
+        #[allow(dead_code)]
+        count: bool,
+    }
+
+    // A regex that is not a literal is checked when it is first compiled.
+    const PEAR_COUNT: &str = r"^there are (\d+) pears$";
+
+    /// Runs these tests again with `args`, in a process of their own, returning
+    /// whether they passed and what they wrote to stdout.
+    fn run_again(args: &[&str]) -> (bool, String) {
+        let output = std::process::Command::new(std::env::current_exe().unwrap())
+            .args(args)
+            .output()
+            .unwrap();
+        (
+            output.status.success(),
+            String::from_utf8_lossy(&output.stdout).into_owned(),
+        )
+    }
+
+    /// Runs these tests again with `args`, writing the report of `format` to a
+    /// file, and returns whether they passed and the report.
+    fn report(format: &str, args: &[&str]) -> (bool, String) {
+        let path = std::env::temp_dir().join(format!("cucumber-report-{}.{}", std::process::id(), format));
+        let format = format!("{}:{}", format, path.display());
+        let args = ["--color", "never", "--format", &format].iter().chain(args).copied().collect::<Vec<_>>();
+
+        let (is_success, _) = run_again(&args);
+        let report = std::fs::read_to_string(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        (is_success, report)
+    }
 
     steps!(crate::MyWorld => {
         when regex "thing (\\d+) does (.+)" (usize, String) |_world, _sz, _txt, _step| {
 
         };
 
+        then regex r"^the (\w+) count is (?:(\d+)|unknown)$" (String, Option<usize>) |_world, thing, count, step| {
+            assert_eq!(thing, "apple");
+
+            if step.value.ends_with("unknown") {
+                assert_eq!(count, None);
+            } else {
+                assert_eq!(count, Some(3));
+            }
+        };
+
+        then regex r"^(?P<owner>\w+) has (?P<count>\d+)(?: (?P<colour>\w+))? apples$" { Basket } |_world, basket, _step| {
+            assert_eq!(basket.owner, "alice");
+            assert_eq!(basket.count, 3);
+
+            if let Some(colour) = basket.colour {
+                assert_eq!(colour, "green");
+            }
+        };
+
+        then regex PEAR_COUNT (usize) |_world, count, _step| {
+            assert_eq!(count, 2);
+        };
+
+        // Of several matching regexes, the first in the order of their step
+        // definitions runs, as listed in the reports.
+        then regex r"^several (.+) match$" |_world, _matches, _step| {};
+
+        then regex r"^several regexes (.+)$" |_world, _matches, _step| {
+            panic!("the regex listed second ran");
+        };
+
+        then "constant regexes are checked when first compiled" |_world, _step| {
+            let compiled = std::panic::catch_unwind(|| {
+                cucumber_rust::step_regex!(cucumber_rust, PEAR_COUNT, (usize, String)).as_str()
+            });
+
+            assert!(compiled.is_err());
+        };
+
         when regex "^test (.*) regex$" |_world, matches, _step| {
             println!("{}", matches[1]);
         };
@@ -60,6 +166,350 @@ mod basic {
             assert_eq!(expected_keys, vec!["a", "b"]);
             assert_eq!(expected_values, vec!["fizz", "buzz"]);
         };
+
+        then "tables can be read as structs" |_world, step| {
+            let table = step.table().unwrap();
+
+            let rows = table.rows_as::<Row>().unwrap();
+            assert_eq!(rows, vec![
+                Row { name: "alice".into(), count: 3 },
+                Row { name: "bob".into(), count: 5 },
+            ]);
+
+            let hashes = table.hashes();
+            assert_eq!(hashes[1]["name"], "bob");
+
+            let transposed = table.transpose();
+            assert_eq!(transposed.header, vec!["name", "alice", "bob"]);
+            assert_eq!(transposed.rows, vec![vec!["count", "3", "5"]]);
+
+            let error = table.rows_as::<BadRow>().unwrap_err();
+            assert_eq!(error.line, table.position.0 + 2);
+            assert_eq!(error.column.as_deref(), Some("count"));
+        };
+
+        then "tables can be read as keys and values" |_world, step| {
+            let map = step.table().unwrap().rows_hash().unwrap();
+
+            assert_eq!(map["a"], "fizz");
+            assert_eq!(map["b"], "buzz");
+        };
+
+        then "docstrings can be parsed by content type" |_world, step| {
+            assert_eq!(step.docstring_content_type().as_deref(), Some("json"));
+
+            let config = step.parse_docstring::<Config>().unwrap();
+            assert_eq!(config, Config { name: "cucumber".into(), retries: 3 });
+
+            let error = step.docstring_json::<Vec<u32>>().unwrap_err();
+            assert_eq!(error.line, step.position.0 + 2);
+        };
+
+        then "docstrings keep their content type past comments and threads" |_world, step| {
+            let step = step.clone();
+
+            std::thread::spawn(move || {
+                assert_eq!(step.docstring_content_type().as_deref(), Some("json"));
+
+                // The docstring opens after a comment.
+                let error = step.docstring_json::<Vec<u32>>().unwrap_err();
+                assert_eq!(error.line, step.position.0 + 3);
+            })
+            .join()
+            .unwrap();
+        };
+
+        then "docstrings in outlines keep their content type" |_world, step| {
+            assert_eq!(step.docstring_content_type().as_deref(), Some("json"));
+
+            let name = step.parse_docstring::<HashMap<String, String>>().unwrap();
+            assert_eq!(name["name"], "cucumber");
+        };
+
+        then regex r"^the count output counts (\d+) scenarios? run at (.+)$" (usize, String) |_world, count, location, _step| {
+            // `--format count` selects the output `setup` registers.
+            let (is_success, stdout) = run_again(&["--format", "count", &location]);
+
+            // Hooks print before the output reports, once the run is over.
+            assert!(is_success);
+            assert_eq!(stdout.lines().last(), Some(format!("{} scenarios run", count).as_str()));
+        };
+
+        then regex r"^running (.+) in a random order prints the seed to repeat it with$" (String) |_world, locations, _step| {
+            let locations = locations.split(' ').collect::<Vec<_>>();
+            let seed_line = |stdout: &str| {
+                stdout
+                    .lines()
+                    .find(|line| line.starts_with("Randomized with seed "))
+                    .map(str::to_string)
+            };
+            let scenarios = |stdout: &str| {
+                stdout
+                    .lines()
+                    .filter(|line| line.starts_with(" Scenario:"))
+                    .map(str::to_string)
+                    .collect::<Vec<_>>()
+            };
+
+            let (is_success, stdout) = run_again(&[&["--order", "random"], &locations[..]].concat());
+            assert!(is_success);
+            let line = seed_line(&stdout).unwrap();
+            let seed = line["Randomized with seed ".len()..].split(' ').next().unwrap();
+            assert_eq!(line, format!("Randomized with seed {0} (--order random:{0})", seed));
+            assert_eq!(scenarios(&stdout).len(), locations.len());
+
+            let order = format!("random:{}", seed);
+            let (is_success, again) = run_again(&[&["--order", &order], &locations[..]].concat());
+            assert!(is_success);
+            assert_eq!(seed_line(&again), Some(line.clone()));
+            assert_eq!(scenarios(&again), scenarios(&stdout));
+        };
+
+        when regex r"^a step prints (.+) and fails$" (String) |_world, text, _step| {
+            println!("{}", text);
+            panic!("it failed");
+        };
+
+        when "the process aborts" |_world, _step| {
+            std::process::abort();
+        };
+
+        when "a step prints a line that looks like an event" |_world, _step| {
+            println!("\u{1e}cucumber-event:not an event");
+        };
+
+        then regex r"^running (.+) isolated reports the abort and runs the rest$" (String) |_world, location, _step| {
+            // Three scenarios to a process, so that the two after the abort are
+            // run in a new one.
+            let rerun = std::env::temp_dir().join(format!("cucumber-isolation-{}.rerun", std::process::id()));
+            let (is_success, stdout) = run_again(&[
+                "--isolate", "--isolate-batch", "3", "--nocapture", "--color", "never",
+                "--rerun-file", &rerun.display().to_string(), &location,
+            ]);
+            let failed = std::fs::read_to_string(&rerun).unwrap();
+            let _ = std::fs::remove_file(&rerun);
+
+            assert!(!is_success);
+            assert!(stdout.contains("Isolated process crashed: "));
+            assert!(stdout.contains("Isolated process crashed after the steps: "));
+            #[cfg(unix)]
+            assert!(stdout.contains("SIGABRT"));
+            assert!(stdout.contains("\u{1e}cucumber-event:not an event\n"));
+            assert!(stdout.contains("4 scenarios (2 failed, 2 passed)"));
+            // The crash in the after hook fails its scenario in the reports too.
+            assert_eq!(failed, format!("{0}:6\n{0}:19\n", location));
+        };
+
+        then regex r"^the json report of (.+) has its scenarios and their steps$" (String) |_world, location, _step| {
+            let (_, report) = report("json", &[&location]);
+            let report: serde_json::Value = serde_json::from_str(&report).unwrap();
+            let feature = &report[0];
+
+            assert_eq!(feature["uri"], location);
+            assert_eq!(feature["keyword"], "Feature");
+            assert_eq!(feature["name"], "Reports");
+            assert_eq!(feature["line"], 2);
+            assert_eq!(feature["tags"][0]["name"], "@reported");
+
+            // Every scenario is preceded by the background it ran.
+            let elements = feature["elements"].as_array().unwrap();
+            let kinds = elements
+                .iter()
+                .map(|e| (e["keyword"].as_str().unwrap(), e["type"].as_str().unwrap()))
+                .collect::<Vec<_>>();
+            assert_eq!(kinds.len(), 10);
+            assert_eq!(kinds[0], ("Background", "background"));
+            assert_eq!(kinds[1], ("Scenario", "scenario"));
+            assert_eq!(kinds[7], ("Scenario Outline", "scenario"));
+            assert_eq!(elements[1]["id"], "reports;passing");
+            assert_eq!(elements[1]["line"], 10);
+            // A row of examples is positioned at its row.
+            assert_eq!(elements[7]["line"], 26);
+
+            let background_step = &elements[0]["steps"][0];
+            assert_eq!(background_step["keyword"], "Given ");
+            assert_eq!(background_step["name"], "a thing");
+            assert_eq!(background_step["line"], 8);
+            assert_eq!(background_step["result"]["status"], "passed");
+            assert!(background_step["result"]["duration"].is_u64());
+
+            let failing = &elements[3]["steps"];
+            assert_eq!(failing[0]["result"]["status"], "failed");
+            let message = failing[0]["result"]["error_message"].as_str().unwrap();
+            assert!(message.starts_with("it failed\n"), "{}", message);
+            assert_eq!(failing[1]["result"]["status"], "skipped");
+            assert!(failing[1]["result"].get("duration").is_none());
+            assert_eq!(elements[5]["steps"][0]["result"]["status"], "undefined");
+            assert_eq!(elements[7]["steps"][0]["name"], "another thing");
+        };
+
+        then regex r"^the json report of (.+) leaves it out$" (String) |_world, location, _step| {
+            let (is_success, report) = report("json", &[&location]);
+
+            // It is reported by the other outputs, as a failed run.
+            assert!(!is_success);
+            assert_eq!(report, "[]\n");
+        };
+
+        then regex r"^the junit report of (.+) has a testcase per scenario$" (String) |_world, location, _step| {
+            let (_, report) = report("junit", &[&location]);
+
+            assert!(report.starts_with("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n"));
+            assert!(report.contains(
+                "<testsuites name=\"cucumber\" tests=\"5\" failures=\"1\" errors=\"0\" skipped=\"1\""
+            ));
+            assert!(report.contains(
+                "<testsuite name=\"Reports\" tests=\"5\" failures=\"1\" errors=\"0\" skipped=\"1\""
+            ));
+            assert!(report.contains(&format!("file=\"{}\">\n", location)));
+            assert!(report.contains("<testcase name=\"passing\" classname=\"Reports\""));
+            assert!(report.contains(&format!("file=\"{}\" line=\"10\"></testcase>", location)));
+            assert!(report.contains("<testcase name=\"outline (example 1)\""));
+            assert!(report.contains("<testcase name=\"A rule / in a rule\""));
+            assert!(report.contains(
+                "<skipped message=\"Step not yet implemented: When a step that does not exist\"/>"
+            ));
+            assert!(report.contains(
+                "<failure message=\"it failed\" type=\"panic\">Step failed: When a step prints &lt;b&gt;&amp; and fails\nit failed\n"
+            ));
+            assert!(report.contains("<system-out>&lt;b&gt;&amp;\n</system-out>"));
+            assert!(report.ends_with("  </testsuite>\n</testsuites>\n"));
+        };
+
+        then regex r"^the html report of (.+) has its scenarios by status$" (String) |_world, location, _step| {
+            let (_, report) = report("html", &[&location]);
+
+            assert!(report.starts_with("<!DOCTYPE html>\n"));
+            assert!(report.contains(
+                r#"<span>5 scenarios</span><span class="passed">3 passed</span><span class="failed">1 failed</span><span class="skipped">0 skipped</span><span class="undefined">1 undefined</span>"#
+            ));
+            assert!(report.contains(r#"<option value="@broken">@broken</option>"#));
+            assert!(report.contains(r#"<details class="feature failed" open>"#));
+            assert!(report.contains(&format!(
+                r#"<summary>Feature: Reports<span class="tag">@reported</span> <span class="location">{}</span></summary>"#,
+                location
+            )));
+            assert!(report.contains(
+                r#"<details class="scenario passed" data-status="passed" data-tags="@reported">"#
+            ));
+            assert!(report.contains(
+                r#"<details class="scenario failed" data-status="failed" data-tags="@reported @broken" open>"#
+            ));
+            assert!(report.contains(
+                r#"<details class="scenario undefined" data-status="undefined" data-tags="@reported">"#
+            ));
+            assert!(report.contains(r#"<div class="step skipped">"#));
+            assert!(report.contains("<pre class=\"error\">it failed\ntests/cucumber.rs:"));
+            assert!(report.contains(
+                "<details><summary>Captured stdout</summary><pre>&lt;b&gt;&amp;\n</pre></details>"
+            ));
+            assert!(report.contains("<b>When</b> a step prints &lt;b&gt;&amp; and fails"));
+            assert!(report.ends_with("</body>\n</html>\n"));
+        };
+
+        then regex r"^the message report of (.+) links pickles, test cases and steps$" (String) |_world, location, _step| {
+            let (_, report) = report("message", &[&location]);
+            let envelopes = report
+                .lines()
+                .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+                .collect::<Vec<_>>();
+            let kinds = envelopes
+                .iter()
+                .map(|e| e.as_object().unwrap().keys().next().unwrap().as_str())
+                .collect::<Vec<_>>();
+            let find = |kind: &str| {
+                envelopes.iter().filter_map(|e| e.get(kind)).collect::<Vec<_>>()
+            };
+
+            // The step definitions come after the meta, before anything is run.
+            assert_eq!(envelopes[0]["meta"]["protocolVersion"], "22.0.0");
+            let definitions = kinds[1..].iter().take_while(|k| **k == "stepDefinition").count();
+            assert!(definitions > 0);
+            // Step definitions have no source to refer to.
+            assert!(find("stepDefinition")[0].get("sourceReference").is_none());
+            assert_eq!(
+                kinds[1 + definitions..1 + definitions + 11],
+                [
+                    "testRunStarted",
+                    "source",
+                    "gherkinDocument",
+                    "pickle",
+                    "testCase",
+                    "testCaseStarted",
+                    "testStepStarted",
+                    "testStepFinished",
+                    "testStepStarted",
+                    "testStepFinished",
+                    "testCaseFinished",
+                ]
+            );
+            assert_eq!(envelopes.last().unwrap()["testRunFinished"]["success"], false);
+
+            let pickles = find("pickle");
+            assert_eq!(pickles[0]["id"], format!("{}:10:13#pickle", location));
+            assert_eq!(pickles[0]["astNodeIds"][0], format!("{}:10:13", location));
+            // A row of examples is linked to its outline and to its row.
+            assert_eq!(
+                pickles[3]["astNodeIds"],
+                serde_json::json!([format!("{}:21:21", location), format!("{}:26:5", location)])
+            );
+            assert_eq!(pickles[3]["steps"][1]["text"], "another thing");
+
+            let test_cases = find("testCase");
+            assert_eq!(test_cases[0]["pickleId"], pickles[0]["id"]);
+            let test_step = &test_cases[0]["testSteps"][0];
+            assert_eq!(test_step["pickleStepId"], pickles[0]["steps"][0]["id"]);
+            assert_eq!(test_step["stepDefinitionIds"][0], "Given a thing");
+
+            let started = find("testCaseStarted");
+            assert_eq!(started[0]["testCaseId"], test_cases[0]["id"]);
+            let finished = find("testStepFinished");
+            assert_eq!(finished[0]["testCaseStartedId"], started[0]["id"]);
+            assert_eq!(finished[0]["testStepId"], test_step["id"]);
+            assert_eq!(finished[0]["testStepResult"]["status"], "PASSED");
+        };
+
+        then regex r"^the rerun file of (.+) lists its failed scenario$" (String) |_world, location, _step| {
+            let (_, report) = report("rerun", &[&location]);
+
+            // Undefined steps leave their scenario out, as they did not fail.
+            assert_eq!(report, format!("{}:14\n", location));
+        };
+
+        then regex r"^running (.+) in 2 shards runs each feature in one of them$" (String) |_world, locations, _step| {
+            let names = ["1/2", "2/2"]
+                .iter()
+                .map(|shard| {
+                    let mut args = locations.split_whitespace().collect::<Vec<_>>();
+                    args.extend(&["--shard", shard]);
+                    let (_, report) = report("json", &args);
+                    let report: serde_json::Value = serde_json::from_str(&report).unwrap();
+                    report
+                        .as_array()
+                        .unwrap()
+                        .iter()
+                        .map(|feature| feature["name"].as_str().unwrap().to_string())
+                        .collect::<Vec<_>>()
+                })
+                .collect::<Vec<_>>();
+
+            // Features without a scenario in the shard are left out.
+            assert_eq!(names[0].len(), 1);
+            assert_eq!(names[1].len(), 1);
+            assert_ne!(names[0], names[1]);
+        };
+
+        then "a panic in a thread it spawns goes to the panic hook" |_world, _step| {
+            let calls = crate::PANIC_HOOK_CALLS.load(std::sync::atomic::Ordering::SeqCst);
+
+            let joined = std::thread::spawn(|| panic!("panic in a spawned thread")).join();
+
+            assert!(joined.is_err());
+            assert_eq!(
+                crate::PANIC_HOOK_CALLS.load(std::sync::atomic::Ordering::SeqCst),
+                calls + 1
+            );
+        };
     });
 }
 
@@ -77,7 +527,71 @@ after!(after_thing => |_scenario| {
 
 });
 
-fn setup() {}
+after!(abort_after => |scenario| {
+    if scenario.name == "a scenario whose after hook aborts" {
+        std::process::abort();
+    }
+});
+
+// A third-party output, made available as `--format count`.
+struct ScenarioCount(usize);
+
+impl OutputVisitor for ScenarioCount {
+    fn visit_start(&mut self) {}
+    fn visit_feature(&mut self, _feature: &gherkin::Feature, _path: &Path) {}
+    fn visit_feature_end(&mut self, _feature: &gherkin::Feature, _duration: Duration) {}
+    fn visit_feature_error<'a>(&mut self, _path: &Path, _error: &gherkin::Error<'a>) {}
+    fn visit_rule(&mut self, _rule: &gherkin::Rule) {}
+    fn visit_rule_end(&mut self, _rule: &gherkin::Rule) {}
+    fn visit_scenario(&mut self, _rule: Option<&gherkin::Rule>, _scenario: &gherkin::Scenario) {
+        self.0 += 1;
+    }
+    fn visit_scenario_end(
+        &mut self,
+        _rule: Option<&gherkin::Rule>,
+        _scenario: &gherkin::Scenario,
+        _duration: Duration,
+    ) {
+    }
+    fn visit_scenario_skipped(
+        &mut self,
+        _rule: Option<&gherkin::Rule>,
+        _scenario: &gherkin::Scenario,
+    ) {
+    }
+    fn visit_step(
+        &mut self,
+        _rule: Option<&gherkin::Rule>,
+        _scenario: &gherkin::Scenario,
+        _step: &gherkin::Step,
+    ) {
+    }
+    fn visit_step_result(
+        &mut self,
+        _rule: Option<&gherkin::Rule>,
+        _scenario: &gherkin::Scenario,
+        _step: &gherkin::Step,
+        _result: &TestResult,
+        _duration: Duration,
+    ) {
+    }
+    fn visit_finish(&mut self, _duration: Duration) {
+        println!("{} scenarios run", self.0);
+    }
+}
+
+// Calls to the panic hook set in `setup`, which steps must leave in place.
+static PANIC_HOOK_CALLS: AtomicUsize = AtomicUsize::new(0);
+
+fn setup() {
+    register_output("count", |_config| Ok(Box::new(ScenarioCount(0))));
+
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        PANIC_HOOK_CALLS.fetch_add(1, Ordering::SeqCst);
+        default_hook(info);
+    }));
+}
 
 cucumber! {
     features: "./features",
@@ -87,5 +601,5 @@ cucumber! {
     ],
     setup: setup,
     before: &[before_thing, some_before, something_great],
-    after: &[after_thing]
+    after: &[after_thing, abort_after]
 }
@@ -0,0 +1 @@
+Scenario: without a feature
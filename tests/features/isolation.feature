@@ -0,0 +1,20 @@
+Feature: Isolation
+
+  # Only run in child processes, by the scenario of features/test.feature
+  # that runs this feature with --isolate, as it aborts the process.
+
+  Scenario: a step that aborts
+    Given a thing
+    When the process aborts
+    Then another thing
+
+  Scenario: a step that prints what looks like an event
+    Given a thing
+    When a step prints a line that looks like an event
+
+  Scenario: a scenario after the abort
+    Given a thing
+    When something goes right
+
+  Scenario: a scenario whose after hook aborts
+    Given a thing
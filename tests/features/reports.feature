@@ -0,0 +1,31 @@
+@reported
+Feature: Reports
+
+  # Only run in child processes, by the scenarios of features/test.feature
+  # that check the reports written of it.
+
+  Background:
+    Given a thing
+
+  Scenario: passing
+    When something goes right
+
+  @broken
+  Scenario: failing
+    When a step prints <b>& and fails
+    Then another thing
+
+  Scenario: undefined
+    When a step that does not exist
+
+  Scenario Outline: outline
+    Then <what>
+
+    Examples:
+      | what          |
+      | another thing |
+
+  Rule: A rule
+
+    Scenario: in a rule
+      Given a thing
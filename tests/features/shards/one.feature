@@ -0,0 +1,4 @@
+Feature: One
+
+  Scenario: one
+    Given a thing
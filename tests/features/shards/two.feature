@@ -0,0 +1,4 @@
+Feature: Two
+
+  Scenario: two
+    Given a thing